@@ -0,0 +1,115 @@
+use anyhow::Context;
+use std::path::Path;
+
+use crate::ignore::wildmatch;
+
+/// the `text` attribute of a path, deciding whether its line endings are normalized
+/// to LF when it's stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Text {
+    /// `text`: always a text file
+    Set,
+    /// `-text` or `binary`: stored byte for byte
+    Unset,
+    /// `text=auto`, or nothing specified: normalized if the content looks like text
+    #[default]
+    Auto,
+}
+
+/// the `text` settings of the `.gitattributes` file at the repository root
+#[derive(Debug, Default, Clone)]
+pub struct Attributes {
+    rules: Vec<(String, Text)>,
+}
+
+impl Attributes {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// reads the `.gitattributes` of the repository `root`, a missing file sets nothing
+    pub fn load(root: &Path) -> anyhow::Result<Self> {
+        let path = root.join(".gitattributes");
+        if !path.is_file() {
+            return Ok(Self::new());
+        }
+        let content = std::fs::read_to_string(&path)
+            .context(format!("failed to read attributes file {path:?}"))?;
+        Ok(Self::parse(&content))
+    }
+
+    /// parses `<pattern> <attribute>...` lines, keeping those that touch `text`
+    pub fn parse(content: &str) -> Self {
+        let mut rules = Vec::new();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next().filter(|p| !p.starts_with('#')) else {
+                continue;
+            };
+            // within a line, later attributes override earlier ones
+            let text = fields.fold(None, |text, attribute| match attribute {
+                "text" | "eol=lf" | "eol=crlf" => Some(Text::Set),
+                "-text" | "binary" => Some(Text::Unset),
+                "text=auto" | "!text" => Some(Text::Auto),
+                _ => text,
+            });
+            if let Some(text) = text {
+                rules.push((pattern.trim_start_matches('/').to_owned(), text));
+            }
+        }
+        Self { rules }
+    }
+
+    /// the `text` attribute of `path`, relative to the repository root. like with
+    /// ignore rules, patterns without a `/` match the file name only and the last
+    /// matching line wins.
+    pub fn text(&self, path: &Path) -> Text {
+        let full = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| match pattern.contains('/') {
+                true => wildmatch(pattern.as_bytes(), full.as_bytes()),
+                false => wildmatch(pattern.as_bytes(), name.as_bytes()),
+            })
+            .map(|(_, text)| *text)
+            .unwrap_or_default()
+    }
+}
+
+/// git only looks this far into a file to tell whether it's binary
+const BINARY_PROBE_LEN: usize = 8000;
+
+/// turns the CRLF line endings of `content` into LF, as git does when storing a text
+/// file. with `Text::Auto`, content that looks binary or holds a lone CR is left as is,
+/// since converting it would not round-trip.
+pub(crate) fn normalize_eol(content: Vec<u8>, text: Text) -> Vec<u8> {
+    if text == Text::Unset || !content.contains(&b'\r') {
+        return content;
+    }
+    if text == Text::Auto {
+        let probe = &content[..content.len().min(BINARY_PROBE_LEN)];
+        let lone_cr = content
+            .iter()
+            .enumerate()
+            .any(|(i, c)| *c == b'\r' && content.get(i + 1) != Some(&b'\n'));
+        if probe.contains(&0) || lone_cr {
+            return content;
+        }
+    }
+    let mut normalized = Vec::with_capacity(content.len());
+    for (i, c) in content.iter().enumerate() {
+        if *c != b'\r' || content.get(i + 1) != Some(&b'\n') {
+            normalized.push(*c);
+        }
+    }
+    normalized
+}
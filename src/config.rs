@@ -0,0 +1,403 @@
+use anyhow::Context;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+/// the settings of a git `config` file
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    /// section and key names are case insensitive and kept lowercased, subsections
+    /// such as the `origin` of `[remote "origin"]` are not
+    section: String,
+    subsection: Option<String>,
+    key: String,
+    value: String,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// reads the `config` file of `git_dir`, a missing file holds no settings
+    pub fn load(git_dir: &Path) -> anyhow::Result<Self> {
+        let path = git_dir.join("config");
+        if !path.is_file() {
+            return Ok(Self::new());
+        }
+        let content = std::fs::read_to_string(&path).context("failed to read the config file")?;
+        Self::parse(&content)
+    }
+
+    /// reads the per-user settings, from the file `GIT_CONFIG_GLOBAL` names or else
+    /// `~/.gitconfig`. a missing file holds no settings.
+    pub fn load_global() -> anyhow::Result<Self> {
+        let path = match std::env::var_os("GIT_CONFIG_GLOBAL") {
+            Some(path) => PathBuf::from(path),
+            None => match std::env::var_os("HOME") {
+                Some(home) => Path::new(&home).join(".gitconfig"),
+                None => return Ok(Self::new()),
+            },
+        };
+        if !path.is_file() {
+            return Ok(Self::new());
+        }
+        let content = std::fs::read_to_string(&path)
+            .context(format!("failed to read the config file {path:?}"))?;
+        Self::parse(&content)
+    }
+
+    /// parses `[section]` or `[section "subsection"]` headers followed by `key = value`
+    /// lines. `#` and `;` start comments, and a key without a value is `true`.
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        let mut section: Option<(String, Option<String>)> = None;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                section = Some(parse_header(header)?);
+                continue;
+            }
+            let Some((name, subsection)) = &section else {
+                anyhow::bail!("config key outside of a section [{line}]");
+            };
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value)?),
+                None => (strip_comment(line).trim(), "true".to_owned()),
+            };
+            anyhow::ensure!(
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+                "malformed config line [{line}]"
+            );
+            entries.push(Entry {
+                section: name.clone(),
+                subsection: subsection.clone(),
+                key: key.to_ascii_lowercase(),
+                value,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    /// the distinct subsections of `section`, e.g. the remote names of `remote`, in the
+    /// order they first appear
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let mut subsections: Vec<&str> = Vec::new();
+        for entry in &self.entries {
+            if let Some(subsection) = &entry.subsection {
+                if entry.section.eq_ignore_ascii_case(section)
+                    && !subsections.contains(&subsection.as_str())
+                {
+                    subsections.push(subsection);
+                }
+            }
+        }
+        subsections
+    }
+
+    /// writes the settings into the `config` file of `git_dir`. comments and the
+    /// layout of the file it was read from are not kept.
+    pub fn persist(&self, git_dir: &Path) -> anyhow::Result<()> {
+        std::fs::write(git_dir.join("config"), self.to_string())
+            .context("failed to write the config file")
+    }
+
+    /// sets `name`, e.g. `user.name`, to `value`, replacing its last value if it has
+    /// any. otherwise it's added like `add` does.
+    pub fn set(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let entry = Entry::new(name, value)?;
+        match self.entries.iter_mut().rev().find(|e| e.same_key(&entry)) {
+            Some(existing) => existing.value = entry.value,
+            None => self.insert(entry),
+        }
+        Ok(())
+    }
+
+    /// adds another value for `name`, for keys like `remote.<name>.fetch` that may be
+    /// set several times. it goes at the end of the last section it belongs to, or a
+    /// new section at the end of the file.
+    pub fn add(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        self.insert(Entry::new(name, value)?);
+        Ok(())
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        match self.entries.iter().rposition(|e| e.same_section(&entry)) {
+            Some(i) => self.entries.insert(i + 1, entry),
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// looks up a setting by its full name, e.g. `user.name` or `remote.origin.url`.
+    /// when a key is set several times, the last value wins.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let (section, rest) = name.split_once('.')?;
+        let (subsection, key) = match rest.rsplit_once('.') {
+            Some((subsection, key)) => (Some(subsection), key),
+            None => (None, rest),
+        };
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| {
+                e.section.eq_ignore_ascii_case(section)
+                    && e.subsection.as_deref() == subsection
+                    && e.key.eq_ignore_ascii_case(key)
+            })
+            .map(|e| e.value.as_str())
+    }
+}
+
+impl Entry {
+    fn new(name: &str, value: &str) -> anyhow::Result<Self> {
+        let (section, rest) = name
+            .split_once('.')
+            .ok_or(anyhow::anyhow!("key does not contain a section: {name}"))?;
+        let (subsection, key) = match rest.rsplit_once('.') {
+            Some((subsection, key)) => (Some(subsection.to_owned()), key),
+            None => (None, rest),
+        };
+        anyhow::ensure!(
+            !section.is_empty() && !key.is_empty(),
+            "invalid config key: {name}"
+        );
+        Ok(Self {
+            section: section.to_ascii_lowercase(),
+            subsection,
+            key: key.to_ascii_lowercase(),
+            value: value.to_owned(),
+        })
+    }
+
+    fn same_section(&self, other: &Entry) -> bool {
+        self.section == other.section && self.subsection == other.subsection
+    }
+
+    fn same_key(&self, other: &Entry) -> bool {
+        self.same_section(other) && self.key == other.key
+    }
+}
+
+/// lays the settings out like git does, a tab-indented `key = value` line each under
+/// the header of their section
+impl Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut previous: Option<&Entry> = None;
+        for entry in &self.entries {
+            if !previous.is_some_and(|p| p.same_section(entry)) {
+                match &entry.subsection {
+                    Some(subsection) => {
+                        let subsection = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+                        writeln!(f, "[{} \"{subsection}\"]", entry.section)?
+                    }
+                    None => writeln!(f, "[{}]", entry.section)?,
+                }
+            }
+            writeln!(f, "\t{} = {}", entry.key, quote_value(&entry.value))?;
+            previous = Some(entry);
+        }
+        Ok(())
+    }
+}
+
+/// escapes `value` so that `parse_value` reads it back, quoting it when it has
+/// whitespace around it or characters that would start a comment
+fn quote_value(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    let needs_quotes = value.trim() != value || value.contains(['#', ';']);
+    match needs_quotes {
+        true => format!("\"{escaped}\""),
+        false => escaped,
+    }
+}
+
+/// parses what follows the `[` of a section header
+fn parse_header(header: &str) -> anyhow::Result<(String, Option<String>)> {
+    let (header, rest) = header
+        .split_once(']')
+        .ok_or(anyhow::anyhow!("malformed config section [{header}"))?;
+    anyhow::ensure!(
+        strip_comment(rest).trim().is_empty(),
+        "malformed config section [{header}]{rest}"
+    );
+    match header.split_once(|c: char| c.is_ascii_whitespace()) {
+        Some((name, subsection)) => {
+            let subsection = subsection
+                .trim()
+                .strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .ok_or(anyhow::anyhow!("malformed config section [{header}]"))?;
+            let subsection = subsection.replace("\\\"", "\"").replace("\\\\", "\\");
+            Ok((name.to_ascii_lowercase(), Some(subsection)))
+        }
+        // the deprecated `[section.subsection]` form
+        None => match header.split_once('.') {
+            Some((name, subsection)) => Ok((
+                name.to_ascii_lowercase(),
+                Some(subsection.to_ascii_lowercase()),
+            )),
+            None => Ok((header.to_ascii_lowercase(), None)),
+        },
+    }
+}
+
+/// unquotes and unescapes a value, dropping a trailing comment and the whitespace
+/// around it. whitespace inside quotes is kept.
+fn parse_value(raw: &str) -> anyhow::Result<String> {
+    let mut value = String::new();
+    // length of `value` up to its last quoted or escaped character, which trimming
+    // must not cut into
+    let mut kept = 0;
+    let mut quoted = false;
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                kept = value.len();
+            }
+            '#' | ';' if !quoted => break,
+            '\\' => {
+                value.push(match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some(c @ ('"' | '\\')) => c,
+                    _ => anyhow::bail!("bad escape in config value [{raw}]"),
+                });
+                kept = value.len();
+            }
+            c => {
+                value.push(c);
+                if quoted {
+                    kept = value.len();
+                }
+            }
+        }
+    }
+    anyhow::ensure!(!quoted, "unterminated quote in config value [{raw}]");
+    let trimmed = value.trim_end().len().max(kept);
+    value.truncate(trimmed);
+    Ok(value)
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split(['#', ';']).next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a config the way people write it by hand, comments, quotes and odd casing included
+    const CONFIG: &str = r#"# written by hand
+[core]
+	repositoryformatversion = 0
+	FileMode = false ; not on this filesystem
+	bare
+[user]
+	name = "  Jane Doe  "
+	email = jane@example.com # work address
+[remote "origin"]
+	url = https://example.com/repo.git
+	fetch = +refs/heads/*:refs/remotes/origin/*
+[branch.Main]
+	remote = origin
+	merge = "refs/heads/main"
+[alias]
+	lg = "log --format=\"%h\t%s\""
+[user]
+	email = jane@home.example.com
+"#;
+
+    #[test]
+    fn parses_a_representative_config() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.get("core.repositoryformatversion"), Some("0"));
+        assert_eq!(config.get("core.filemode"), Some("false"));
+        assert_eq!(config.get("CORE.FILEMODE"), Some("false"));
+        assert_eq!(config.get("core.bare"), Some("true"));
+        // quotes keep the whitespace around a value
+        assert_eq!(config.get("user.name"), Some("  Jane Doe  "));
+        // the last value wins, even from another section with the same name
+        assert_eq!(config.get("user.email"), Some("jane@home.example.com"));
+        assert_eq!(
+            config.get("remote.origin.url"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(
+            config.get("remote.origin.fetch"),
+            Some("+refs/heads/*:refs/remotes/origin/*")
+        );
+        // the deprecated form lowercases its subsection
+        assert_eq!(config.get("branch.main.merge"), Some("refs/heads/main"));
+        assert_eq!(config.get("alias.lg"), Some("log --format=\"%h\t%s\""));
+        assert_eq!(config.get("user.signingkey"), None);
+        assert_eq!(config.subsections("remote"), ["origin"]);
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let config = Config::parse(CONFIG).unwrap();
+        let reparsed = Config::parse(&config.to_string()).unwrap();
+        for name in [
+            "core.bare",
+            "user.name",
+            "user.email",
+            "remote.origin.url",
+            "branch.main.remote",
+            "alias.lg",
+        ] {
+            assert_eq!(reparsed.get(name), config.get(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_configs() {
+        for content in [
+            "key = value\n",
+            "[core\n\tbare = true\n",
+            "[core] bare = true\n",
+            "[remote origin]\n\turl = x\n",
+            "[user]\n\tname = \"unterminated\n",
+            "[user]\n\tname = bad \\q escape\n",
+            "[user]\n\tfull name = x\n",
+        ] {
+            assert!(Config::parse(content).is_err(), "{content:?}");
+        }
+    }
+
+    #[test]
+    fn sets_and_adds_values_in_their_sections() {
+        let mut config = Config::parse("[user]\n\tname = a\n[core]\n\tbare = false\n").unwrap();
+        config.set("user.email", "a@example.com").unwrap();
+        config.set("user.name", "b").unwrap();
+        config
+            .add("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")
+            .unwrap();
+        assert_eq!(
+            config.to_string(),
+            "[user]\n\tname = b\n\temail = a@example.com\n[core]\n\tbare = false\n\
+             [remote \"origin\"]\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n"
+        );
+        assert!(config.set("nosection", "x").is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use std::fmt::Display;
+
+use crate::ObjectKind;
+
+/// failures of the object store that callers may want to tell apart
+#[derive(Debug, thiserror::Error)]
+pub enum GitError {
+    #[error("{0} is not a valid object name")]
+    InvalidObjectName(String),
+    #[error("short object ID {0} is ambiguous")]
+    AmbiguousObjectName(String),
+    #[error("no object found for {0}")]
+    ObjectNotFound(String),
+    #[error("corrupt object: {0}")]
+    Corrupt(String),
+    #[error("expected a {expected} object, found {found}")]
+    UnexpectedKind {
+        expected: ObjectKind,
+        found: ObjectKind,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl GitError {
+    pub(crate) fn corrupt(reason: impl Display) -> Self {
+        Self::Corrupt(reason.to_string())
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// ignore patterns collected from the `.gitignore` files on the way from the
+/// repository root down to the directory being walked
+#[derive(Debug, Default, Clone)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    /// patterns containing a `/` match against the path relative to `base`,
+    /// others only against the file name
+    anchored: bool,
+    /// directory of the `.gitignore` this pattern came from, relative to the root
+    base: PathBuf,
+}
+
+impl IgnoreRules {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// returns these rules extended with the `.gitignore` found in `dir`, if any.
+    /// `rel` is the path of `dir` relative to the repository root.
+    pub fn with_dir(&self, dir: &Path, rel: &Path) -> anyhow::Result<Self> {
+        let mut rules = self.clone();
+        let path = dir.join(".gitignore");
+        if path.is_file() {
+            let content = std::fs::read_to_string(&path)
+                .context(format!("failed to read ignore file {path:?}"))?;
+            rules.extend(&content, rel);
+        }
+        Ok(rules)
+    }
+
+    /// adds the patterns of a `.gitignore` file living in `base`
+    pub fn extend(&mut self, content: &str, base: &Path) {
+        self.patterns.extend(
+            content
+                .lines()
+                .filter_map(|line| Pattern::parse(line, base)),
+        );
+    }
+
+    /// checks whether `path`, relative to the repository root, is ignored.
+    /// the last matching pattern wins, so a later `!pattern` re-includes a path.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+impl Pattern {
+    fn parse(line: &str, base: &Path) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        // a leading backslash escapes `#` and `!`
+        let line = line.strip_prefix('\\').unwrap_or(line);
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        Some(Self {
+            glob: line.trim_start_matches('/').into(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.into(),
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Ok(path) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let text = match self.anchored {
+            true => path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            false => match path.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => return false,
+            },
+        };
+        wildmatch(self.glob.as_bytes(), text.as_bytes())
+    }
+}
+
+/// glob matching with git's semantics: `*` and `?` stop at `/`, `**` spans directories
+pub(crate) fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) if pattern.starts_with(b"**") => {
+            let rest = &pattern[2..];
+            match rest.strip_prefix(b"/") {
+                // `**/` matches zero or more leading directories
+                Some(rest) => (0..=text.len())
+                    .filter(|&i| i == 0 || text[i - 1] == b'/')
+                    .any(|i| wildmatch(rest, &text[i..])),
+                None => (0..=text.len()).any(|i| wildmatch(rest, &text[i..])),
+            }
+        }
+        (Some(b'*'), _) => {
+            let rest = &pattern[1..];
+            (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != b'/')
+                .any(|i| wildmatch(rest, &text[i..]))
+        }
+        (Some(b'?'), Some(c)) if *c != b'/' => wildmatch(&pattern[1..], &text[1..]),
+        (Some(b'['), Some(c)) => match match_class(&pattern[1..], *c) {
+            Some((true, rest)) => wildmatch(rest, &text[1..]),
+            Some((false, _)) => false,
+            // no closing bracket, treat `[` literally
+            None => *c == b'[' && wildmatch(&pattern[1..], &text[1..]),
+        },
+        (Some(b'\\'), Some(c)) if pattern.get(1) == Some(c) => wildmatch(&pattern[2..], &text[1..]),
+        (Some(p), Some(c)) if p == c => wildmatch(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+/// matches `c` against a bracket expression (without the opening `[`), returning
+/// whether it matched and the pattern after the closing `]`
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, pattern) = match pattern.first() {
+        Some(b'!' | b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    // a `]` right after the opening bracket is part of the set
+    let end = pattern
+        .iter()
+        .skip(1)
+        .position(|p| *p == b']')
+        .map(|i| i + 1)?;
+    let set = &pattern[..end];
+
+    let mut matched = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            matched |= (set[i]..=set[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= set[i] == c;
+            i += 1;
+        }
+    }
+    Some((matched != negated, &pattern[end + 1..]))
+}
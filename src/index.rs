@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Context};
+use sha1::Digest;
+use std::{path::Path, str};
+
+use crate::platform;
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const VERSION: u32 = 2;
+/// size of the fixed-length part of an entry, before the path
+const ENTRY_HEADER_SIZE: usize = 62;
+
+/// the staging area, stored in `.git/index` using the version 2 layout
+#[derive(Debug, Default)]
+pub struct Index {
+    /// entries sorted by path
+    pub entries: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: [u8; 20],
+    pub flags: u16,
+    /// path relative to the repository root, `/` separated
+    pub path: String,
+}
+
+impl Index {
+    /// reads the index file at `path`, an absent file is an empty index
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let raw = std::fs::read(path).context(format!("failed to read the index {path:?}"))?;
+        Self::try_from(raw.as_slice())
+    }
+
+    /// writes the index to `path` along with its trailing checksum
+    pub fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend(SIGNATURE);
+        buf.extend(VERSION.to_be_bytes());
+        buf.extend((self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            entry.write_to(&mut buf);
+        }
+        let checksum: [u8; 20] = sha1::Sha1::digest(&buf).into();
+        buf.extend(checksum);
+        std::fs::write(path, buf).context(format!("failed to write the index {path:?}"))
+    }
+
+    /// stages `entry`, replacing any entry with the same path
+    pub fn add(&mut self, entry: IndexEntry) {
+        match self
+            .entries
+            .binary_search_by(|e| e.path.as_bytes().cmp(entry.path.as_bytes()))
+        {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Index {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: &[u8]) -> Result<Self, Self::Error> {
+        if raw.len() < 12 + 20 {
+            anyhow::bail!("index file is too short");
+        }
+        let (content, checksum) = raw.split_at(raw.len() - 20);
+        if sha1::Sha1::digest(content).as_slice() != checksum {
+            anyhow::bail!("index checksum mismatch");
+        }
+        if &content[..4] != SIGNATURE {
+            anyhow::bail!("not an index file");
+        }
+        let version = read_u32(content, 4)?;
+        if version != VERSION {
+            anyhow::bail!("unsupported index version {version}");
+        }
+        let num_entries = read_u32(content, 8)? as usize;
+
+        let mut entries = Vec::with_capacity(num_entries);
+        let mut ptr = 12;
+        for _ in 0..num_entries {
+            let (entry, nbytes) = IndexEntry::read_from(&content[ptr..])?;
+            entries.push(entry);
+            ptr += nbytes;
+        }
+        // whatever remains are extensions, which are dropped
+        Ok(Self { entries })
+    }
+}
+
+impl IndexEntry {
+    /// builds an entry for the file at `path` whose content hashes to `hash`.
+    /// `name` is the path relative to the repository root.
+    pub fn from_file(path: &Path, name: &str, hash: [u8; 20]) -> anyhow::Result<Self> {
+        let metadata = std::fs::symlink_metadata(path)
+            .context(format!("failed to read metadata of {path:?}"))?;
+        let stat = platform::stat_of(&metadata);
+        Ok(Self {
+            ctime: stat.ctime,
+            mtime: stat.mtime,
+            dev: stat.dev,
+            ino: stat.ino,
+            mode: platform::mode_of(&metadata),
+            uid: stat.uid,
+            gid: stat.gid,
+            size: stat.size,
+            hash,
+            flags: name.len().min(0xfff) as u16,
+            path: name.into(),
+        })
+    }
+
+    fn read_from(raw: &[u8]) -> anyhow::Result<(Self, usize)> {
+        if raw.len() < ENTRY_HEADER_SIZE {
+            anyhow::bail!("index entry is truncated");
+        }
+        let flags = u16::from_be_bytes([raw[60], raw[61]]);
+        let path_end = raw[ENTRY_HEADER_SIZE..]
+            .iter()
+            .position(|c| *c == b'\0')
+            .ok_or(anyhow!("index entry path is not terminated"))?;
+        let path = str::from_utf8(&raw[ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + path_end])?;
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(&raw[40..60]);
+
+        let entry = Self {
+            ctime: (read_u32(raw, 0)?, read_u32(raw, 4)?),
+            mtime: (read_u32(raw, 8)?, read_u32(raw, 12)?),
+            dev: read_u32(raw, 16)?,
+            ino: read_u32(raw, 20)?,
+            mode: read_u32(raw, 24)?,
+            uid: read_u32(raw, 28)?,
+            gid: read_u32(raw, 32)?,
+            size: read_u32(raw, 36)?,
+            hash,
+            flags,
+            path: path.into(),
+        };
+        Ok((entry, Self::padded_len(path_end)))
+    }
+
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        for field in [
+            self.ctime.0,
+            self.ctime.1,
+            self.mtime.0,
+            self.mtime.1,
+            self.dev,
+            self.ino,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.size,
+        ] {
+            buf.extend(field.to_be_bytes());
+        }
+        buf.extend(self.hash);
+        buf.extend(self.flags.to_be_bytes());
+        buf.extend(self.path.as_bytes());
+        let padding = Self::padded_len(self.path.len()) - ENTRY_HEADER_SIZE - self.path.len();
+        buf.resize(buf.len() + padding, 0);
+    }
+
+    /// entries are NUL padded to a multiple of eight bytes, with at least one NUL
+    fn padded_len(path_len: usize) -> usize {
+        (ENTRY_HEADER_SIZE + path_len + 8) & !7
+    }
+}
+
+fn read_u32(raw: &[u8], at: usize) -> anyhow::Result<u32> {
+    let bytes = raw
+        .get(at..at + 4)
+        .ok_or(anyhow!("unexpected end of index"))?;
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
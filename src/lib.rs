@@ -1,7 +1,39 @@
+mod attributes;
+mod config;
+mod error;
+mod ignore;
+mod index;
 mod object;
+mod object_id;
+mod pack_index;
 mod packet;
+pub mod platform;
+mod progress;
+pub mod refs;
+pub mod repo;
+mod signature;
+mod transport;
+mod walk;
 
-pub use object::{Node, NodeKind, Object, ObjectKind, Tree};
+pub use attributes::{Attributes, Text};
+pub use config::Config;
+pub use error::GitError;
+pub use ignore::IgnoreRules;
+pub use index::{Index, IndexEntry};
+pub use object::{
+    Commit, Node, NodeKind, Object, ObjectKind, ObjectReader, Tag, Tree, TreeBuilder,
+};
+pub use object_id::ObjectId;
+pub use pack_index::{PackIndex, PackIndexEntry};
 pub use packet::{
-    IntoPackeLineIterator, Packet, PacketLine, PacketLineBuilder, PacketLineIterator,
+    encode_command, packet_lines, parse_fetch_response, Acknowledgments, Capabilities,
+    IntoPackeLineIterator, Negotiation, PackEntry, Packet, PacketLine, PacketLineBuilder,
+    PacketLineIterator, PacketLineRef, PacketLineRefs, ShallowUpdate, SidebandReader,
+};
+pub use progress::{Progress, Verbosity};
+pub use signature::Signature;
+pub use transport::{
+    negotiate_fetch, read_fetch_response, FetchOptions, HttpTransport, SshTarget, SshTransport,
+    Transport,
 };
+pub use walk::RevWalk;
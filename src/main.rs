@@ -1,17 +1,15 @@
 use anyhow::{anyhow, Context};
 use clap::{Parser, Subcommand};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
 use std::fmt::Write;
 use std::fs;
-use std::io::BufRead;
-use std::os::unix::fs::MetadataExt;
-use std::os::unix::fs::PermissionsExt;
+use std::io::{BufRead, Read};
 use std::path::Path;
 use std::path::PathBuf;
 use std::str;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 use codecrafters_git as git;
-use git::IntoPackeLineIterator;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -22,23 +20,60 @@ struct GitCli {
 
 #[derive(Subcommand)]
 enum GitCmd {
-    Init,
+    Init {
+        #[clap(long)]
+        bare: bool,
+        /// the branch HEAD starts on, instead of `init.defaultBranch` or master
+        #[clap(short = 'b', long)]
+        initial_branch: Option<String>,
+    },
     CatFile {
         #[clap(short)]
         pretty_print: bool,
-        hash: String,
+        #[clap(short = 't')]
+        show_type: bool,
+        #[clap(short = 's')]
+        show_size: bool,
+        /// exit with a zero status if the object exists and is valid, printing nothing
+        #[clap(short = 'e')]
+        exists: bool,
+        /// read the object without checking its type, printing it raw, header included,
+        /// unless -t or -s asks for just a part of the header
+        #[clap(long, conflicts_with_all = ["pretty_print", "exists"])]
+        allow_unknown_type: bool,
+        #[clap(long)]
+        batch: bool,
+        #[clap(long)]
+        batch_check: bool,
+        #[clap(required_unless_present_any = ["batch", "batch_check"])]
+        hash: Option<String>,
     },
     HashObject {
         #[clap(short)]
         write: bool,
-        path: String,
+        /// hash the files byte for byte, without normalizing line endings
+        #[clap(long)]
+        no_filters: bool,
+        /// read the paths to hash from stdin, one per line
+        #[clap(long, conflicts_with = "paths")]
+        stdin_paths: bool,
+        #[clap(required_unless_present = "stdin_paths")]
+        paths: Vec<String>,
     },
     LsTree {
         #[clap(long)]
         name_only: bool,
         hash: String,
     },
-    WriteTree,
+    Add {
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+    WriteTree {
+        /// write the tree of this directory, relative to the repository root, instead
+        #[clap(long)]
+        prefix: Option<PathBuf>,
+    },
     CommitTree {
         #[clap(short)]
         parent: String,
@@ -46,38 +81,211 @@ enum GitCmd {
         message: String,
         tree: String,
     },
+    Checkout {
+        hash: String,
+    },
+    Status,
+    DiffTree {
+        old: String,
+        new: String,
+    },
+    RevParse {
+        name: String,
+    },
+    Show {
+        #[clap(default_value = "HEAD")]
+        name: String,
+    },
+    MergeBase {
+        a: String,
+        b: String,
+    },
+    RevList {
+        #[clap(short = 'n', long)]
+        max_count: Option<usize>,
+        hash: String,
+    },
+    Tag {
+        #[clap(short, long, conflicts_with = "message")]
+        list: bool,
+        #[clap(short)]
+        message: Option<String>,
+        #[clap(requires = "message")]
+        name: Option<String>,
+        #[clap(default_value = "HEAD")]
+        object: String,
+    },
+    Branch {
+        #[clap(short, long)]
+        force: bool,
+        name: Option<String>,
+    },
+    UpdateRef {
+        /// reason for the update, recorded in the reflog
+        #[clap(short)]
+        message: Option<String>,
+        ref_name: String,
+        hash: String,
+    },
+    SymbolicRef {
+        name: String,
+        target: Option<String>,
+    },
+    ShowRef,
+    ForEachRef,
+    CountObjects {
+        #[clap(short)]
+        verbose: bool,
+    },
+    VerifyPack {
+        #[clap(short)]
+        verbose: bool,
+        path: String,
+    },
+    IndexPack {
+        pack: String,
+    },
+    UnpackObjects {
+        pack: String,
+    },
+    LsRemote {
+        url: String,
+    },
+    Fetch {
+        url: String,
+    },
+    Remote {
+        /// show the urls of the remotes
+        #[clap(short, long)]
+        verbose: bool,
+        #[command(subcommand)]
+        cmd: Option<RemoteCmd>,
+    },
     Clone {
+        #[clap(long)]
+        depth: Option<u32>,
+        #[clap(short, long)]
+        branch: Option<String>,
+        #[clap(long)]
+        bare: bool,
+        #[clap(long)]
+        keep_pack: bool,
+        /// don't report progress
+        #[clap(short, long)]
+        quiet: bool,
+        /// report every unpacked object and checked out file
+        #[clap(short, long, conflicts_with = "quiet")]
+        verbose: bool,
         url: String,
         directory: String,
     },
 }
 
+#[derive(Subcommand)]
+enum RemoteCmd {
+    Add { name: String, url: String },
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = GitCli::parse();
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     eprintln!("Logs from your program will appear here!");
 
     match cli.cmd {
-        GitCmd::Init => {
-            init(&PathBuf::from("."))?;
-        }
-        GitCmd::CatFile { pretty_print, hash } => {
-            anyhow::ensure!(pretty_print, "must pass -p flag");
-            let data = cat_file(&hash)?;
-
-            use std::io::Write;
-            std::io::stdout().write_all(&data)?;
+        GitCmd::Init {
+            bare,
+            initial_branch,
+        } => {
+            let git_dir = match std::env::var_os("GIT_DIR") {
+                Some(git_dir) => PathBuf::from(git_dir),
+                None => git_dir_of(Path::new("."), bare),
+            };
+            fs::create_dir_all(&git_dir).context("failed to create the git directory")?;
+            let reinitialized = init_git_dir(&git_dir, bare, initial_branch.as_deref())?;
+            let path = fs::canonicalize(&git_dir)?;
+            match reinitialized {
+                true => println!(
+                    "Reinitialized existing Git repository in {}/",
+                    path.display()
+                ),
+                false => println!("Initialized empty Git repository in {}/", path.display()),
+            }
         }
-        GitCmd::HashObject { write, path } => {
-            let sha1sum = hash_object(write, &path)?;
-            println!("{}", hex::encode(&sha1sum));
+        GitCmd::CatFile {
+            pretty_print,
+            show_type,
+            show_size,
+            exists,
+            allow_unknown_type,
+            batch,
+            batch_check,
+            hash,
+        } => match hash {
+            _ if batch || batch_check => cat_file_batch(batch)?,
+            Some(name) if exists => {
+                let git_dir = git::repo::git_dir();
+                let found = git::refs::resolve(&git_dir, &name)
+                    .is_ok_and(|hash| git::Object::load_in(&git_dir, &hash).is_ok());
+                if !found {
+                    std::process::exit(1);
+                }
+            }
+            Some(name) if allow_unknown_type => {
+                cat_file_raw(&name, show_type, show_size)?;
+            }
+            Some(name) => {
+                let hash = git::refs::resolve(&git::repo::git_dir(), &name)?;
+                if show_type {
+                    println!("{}", git::Object::open(&hash)?.kind);
+                } else if show_size {
+                    println!("{}", git::Object::open(&hash)?.size);
+                } else {
+                    anyhow::ensure!(pretty_print, "must pass -p flag");
+                    cat_file(&hash)?;
+                }
+            }
+            None => anyhow::bail!("must pass an object"),
+        },
+        GitCmd::HashObject {
+            write,
+            no_filters,
+            stdin_paths,
+            paths,
+        } => {
+            let paths = match stdin_paths {
+                true => std::io::stdin().lines().collect::<Result<_, _>>()?,
+                false => paths,
+            };
+            // outside of a repository, there are no attributes to go by
+            let root = git::repo::find_repo_root().ok();
+            let attributes = match &root {
+                Some(root) => git::Attributes::load(root)?,
+                None => git::Attributes::new(),
+            };
+            for path in paths {
+                let text = match (no_filters, &root) {
+                    (true, _) => git::Text::Unset,
+                    (false, Some(root)) => git::repo::relative_to_root(root, Path::new(&path))
+                        .map(|rel| attributes.text(&rel))
+                        .unwrap_or_default(),
+                    (false, None) => git::Text::Auto,
+                };
+                let sha1sum = hash_object(write, &path, text)?;
+                println!("{}", hex::encode(sha1sum));
+            }
         }
         GitCmd::LsTree { name_only, hash } => {
             ls_tree(name_only, &hash)?;
         }
-        GitCmd::WriteTree => {
-            let sha1sum = write_tree(".")?;
-            println!("{}", hex::encode(&sha1sum));
+        GitCmd::Add { paths } => {
+            add(&paths)?;
+        }
+        GitCmd::WriteTree { prefix } => {
+            let sha1sum = write_tree(
+                &git::repo::find_repo_root()?,
+                prefix.as_deref().unwrap_or(Path::new("")),
+            )?;
+            println!("{}", hex::encode(sha1sum));
         }
         GitCmd::CommitTree {
             parent,
@@ -85,32 +293,258 @@ fn main() -> anyhow::Result<()> {
             tree,
         } => {
             let sha1sum = commit_tree(parent, message, tree)?;
-            println!("{}", hex::encode(&sha1sum))
+            println!("{}", hex::encode(sha1sum))
+        }
+        GitCmd::Checkout { hash } => {
+            checkout(&hash, &git::repo::git_dir(), &git::repo::find_repo_root()?)?;
+        }
+        GitCmd::Status => {
+            status(&git::repo::git_dir(), &git::repo::find_repo_root()?)?;
+        }
+        GitCmd::DiffTree { old, new } => {
+            diff_tree(&old, &new, &git::repo::git_dir())?;
+        }
+        GitCmd::RevParse { name } => {
+            println!("{}", git::refs::resolve(&git::repo::git_dir(), &name)?);
+        }
+        GitCmd::Show { name } => {
+            show(&git::repo::git_dir(), &name)?;
+        }
+        GitCmd::MergeBase { a, b } => match merge_base(&git::repo::git_dir(), &a, &b)? {
+            Some(base) => println!("{base}"),
+            // like git, unrelated histories print nothing and fail
+            None => std::process::exit(1),
+        },
+        GitCmd::RevList { max_count, hash } => {
+            rev_list(&hash, max_count)?;
+        }
+        GitCmd::Tag {
+            list,
+            message,
+            name,
+            object,
+        } => match (name, message) {
+            (Some(name), Some(message)) if !list => {
+                tag(&name, &object, &message)?;
+            }
+            _ => list_tags()?,
+        },
+        GitCmd::Branch { force, name } => match name {
+            Some(name) => create_branch(&name, force)?,
+            None => list_branches()?,
+        },
+        GitCmd::UpdateRef {
+            message,
+            ref_name,
+            hash,
+        } => {
+            let message = message.as_deref().unwrap_or_default();
+            git::refs::update_ref(&git::repo::git_dir(), &ref_name, &hash, message)?;
+        }
+        GitCmd::SymbolicRef { name, target } => {
+            let git_dir = &git::repo::git_dir();
+            match target {
+                Some(target) => git::refs::write_symbolic_ref(git_dir, &name, &target)?,
+                None => match git::refs::read_symbolic_ref(git_dir, &name)? {
+                    Some(target) => println!("{target}"),
+                    None => anyhow::bail!("ref {name} is not a symbolic ref"),
+                },
+            }
+        }
+        GitCmd::ShowRef => {
+            for (name, hash) in git::refs::list_refs(&git::repo::git_dir())? {
+                println!("{hash} {name}");
+            }
+        }
+        GitCmd::ForEachRef => {
+            for_each_ref()?;
+        }
+        GitCmd::CountObjects { verbose } => {
+            count_objects(verbose)?;
+        }
+        GitCmd::VerifyPack { verbose, path } => {
+            verify_pack(verbose, &path)?;
+        }
+        GitCmd::IndexPack { pack } => {
+            let checksum = index_pack(Path::new(&pack))?;
+            println!("{}", hex::encode(checksum));
+        }
+        GitCmd::UnpackObjects { pack } => {
+            unpack_objects(&git::repo::git_dir(), Path::new(&pack))?;
+        }
+        GitCmd::LsRemote { url } => {
+            ls_remote(&url)?;
+        }
+        GitCmd::Fetch { url } => {
+            fetch(&git::repo::git_dir(), &url)?;
+        }
+        GitCmd::Remote { verbose, cmd } => match cmd {
+            Some(RemoteCmd::Add { name, url }) => remote_add(&name, &url)?,
+            None => list_remotes(verbose)?,
+        },
+        GitCmd::Clone {
+            depth,
+            branch,
+            bare,
+            keep_pack,
+            quiet,
+            verbose,
+            url,
+            directory,
+        } => {
+            let verbosity = match (quiet, verbose) {
+                (true, _) => git::Verbosity::Quiet,
+                (_, true) => git::Verbosity::Verbose,
+                _ => git::Verbosity::Normal,
+            };
+            let options = CloneOptions {
+                depth,
+                branch,
+                bare,
+                keep_pack,
+                verbosity,
+            };
+            git_clone(&url, &PathBuf::from(directory), &options)?;
+        }
+    }
+    Ok(())
+}
+
+/// lays out a repository in `current_dir`. a bare repository has no working tree and
+/// keeps the git directory contents at the top level instead of under `.git`.
+fn init(current_dir: &Path, bare: bool) -> anyhow::Result<()> {
+    let git_dir = git_dir_of(current_dir, bare);
+    fs::create_dir_all(&git_dir).context("failed to create the git directory")?;
+    init_git_dir(&git_dir, bare, None)?;
+    Ok(())
+}
+
+/// lays out the objects, refs, HEAD and config of an empty repository in `git_dir`,
+/// with HEAD on `initial_branch`, or else `init.defaultBranch` of the user's config or
+/// master. an existing repository only gets what it lacks, keeping its HEAD and
+/// config, and the returned flag tells whether there was one.
+fn init_git_dir(git_dir: &Path, bare: bool, initial_branch: Option<&str>) -> anyhow::Result<bool> {
+    fs::create_dir_all(git_dir.join("objects")).context("failed to create the objects database")?;
+    fs::create_dir_all(git_dir.join("refs")).context("failed to create the refs")?;
+    let existing = git_dir.join("HEAD").is_file();
+    match (existing, initial_branch) {
+        (true, Some(branch)) => {
+            eprintln!("warning: re-init: ignored --initial-branch={branch}");
+        }
+        (true, None) => (),
+        (false, _) => {
+            let branch = match initial_branch {
+                Some(branch) => branch.to_owned(),
+                None => git::Config::load_global()?
+                    .get("init.defaultbranch")
+                    .unwrap_or("master")
+                    .to_owned(),
+            };
+            git::refs::write_symbolic_ref(git_dir, "HEAD", &format!("refs/heads/{branch}"))
+                .context("failed to specify the HEAD")?;
+        }
+    }
+    if git_dir.join("config").is_file() {
+        return Ok(existing);
+    }
+
+    let mut config = git::Config::new();
+    config.set("core.repositoryformatversion", "0")?;
+    // without an execute bit, file modes can't be trusted
+    config.set("core.filemode", &cfg!(unix).to_string())?;
+    config.set("core.bare", &bare.to_string())?;
+    if !bare {
+        config.set("core.logallrefupdates", "true")?;
+    }
+    config.persist(git_dir)?;
+    Ok(existing)
+}
+
+fn git_dir_of(current_dir: &Path, bare: bool) -> PathBuf {
+    match bare {
+        true => current_dir.to_owned(),
+        false => current_dir.join(".git"),
+    }
+}
+
+/// prints the content of the object: trees like `ls-tree` and everything else as it is
+/// stored. commits and tags are parsed first to check them, but printed raw so headers
+/// like `gpgsig`, `encoding` and `mergetag` are kept.
+fn cat_file(hash: &str) -> anyhow::Result<()> {
+    use std::io::{Read, Write};
+
+    let mut reader = git::Object::open(hash)?;
+    if reader.kind == git::ObjectKind::Blob {
+        std::io::copy(&mut reader, &mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+    let mut body = Vec::with_capacity(reader.size);
+    reader.read_to_end(&mut body)?;
+    let obj = git::Object::new(reader.kind.clone(), body);
+    match obj.kind() {
+        git::ObjectKind::Tree => print!("{}", git::Tree::try_from(obj)?),
+        git::ObjectKind::Commit => {
+            git::Commit::try_from(obj.clone())?;
+            std::io::stdout().lock().write_all(&obj.body)?;
         }
-        GitCmd::Clone { url, directory } => {
-            git_clone(&url, &PathBuf::from(directory))?;
+        git::ObjectKind::Tag => {
+            git::Tag::try_from(obj.clone())?;
+            std::io::stdout().lock().write_all(&obj.body)?;
         }
+        git::ObjectKind::Blob => unreachable!("blobs are streamed above"),
     }
     Ok(())
 }
 
-fn init(current_dir: &Path) -> anyhow::Result<()> {
-    fs::create_dir(current_dir.join(".git")).context("failed to create the git directory")?;
-    fs::create_dir(current_dir.join(".git/objects"))
-        .context("failed to create the objects database")?;
-    fs::create_dir(current_dir.join(".git/refs")).context("failed to create the refs")?;
-    fs::write(current_dir.join(".git/HEAD"), "ref: refs/heads/master\n")
-        .context("failed to specify the HEAD")?;
+/// prints the object `name` as it is stored, `kind size\0` header and all, or only the
+/// kind or size of its header, none of which is checked
+fn cat_file_raw(name: &str, show_type: bool, show_size: bool) -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    let hash = git::refs::resolve(git_dir, name)?;
+    let raw = git::Object::load_raw_in(git_dir, &hash)?;
+    if !show_type && !show_size {
+        use std::io::Write;
+        return Ok(std::io::stdout().write_all(&raw)?);
+    }
+    let header = raw.split(|&c| c == 0).next().unwrap_or_default();
+    let header = String::from_utf8_lossy(header);
+    let (kind, size) = header.split_once(' ').unwrap_or((&header, ""));
+    match show_type {
+        true => println!("{kind}"),
+        false => println!("{size}"),
+    }
     Ok(())
 }
 
-fn cat_file(hash: &str) -> anyhow::Result<Vec<u8>> {
-    let obj = git::Object::load(hash)?;
-    Ok(obj.body)
+/// prints `<hash> <type> <size>`, followed by the content unless `with_content` is
+/// false, for every object named on stdin
+fn cat_file_batch(with_content: bool) -> anyhow::Result<()> {
+    use std::io::{BufRead, Write};
+
+    let git_dir = &git::repo::git_dir();
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let name = line.trim();
+        let object = git::refs::resolve(git_dir, name)
+            .ok()
+            .and_then(|hash| Some((git::Object::open_in(git_dir, &hash).ok()?, hash)));
+        let Some((mut object, hash)) = object else {
+            writeln!(stdout, "{name} missing")?;
+            continue;
+        };
+        writeln!(stdout, "{hash} {} {}", object.kind, object.size)?;
+        if with_content {
+            std::io::copy(&mut object, &mut stdout)?;
+            writeln!(stdout)?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
 }
 
-fn hash_object(write: bool, path: &str) -> anyhow::Result<[u8; 20]> {
-    let obj = git::Object::new_blob_from_file(path)?;
+fn hash_object(write: bool, path: &str, text: git::Text) -> anyhow::Result<[u8; 20]> {
+    let obj = git::Object::new_blob_from_file(path, text)?;
 
     if write {
         obj.persist()?;
@@ -118,74 +552,225 @@ fn hash_object(write: bool, path: &str) -> anyhow::Result<[u8; 20]> {
     Ok(obj.hash())
 }
 
-fn ls_tree(name_only: bool, hash: &str) -> anyhow::Result<()> {
-    let obj = git::Object::load(hash)?;
+/// lists the entries of the tree `name` resolves to, or of the tree of a commit
+fn ls_tree(name_only: bool, name: &str) -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    let mut obj = git::Object::load_in(git_dir, &git::refs::resolve(git_dir, name)?)?;
+    if *obj.kind() == git::ObjectKind::Commit {
+        let tree = git::Commit::try_from(obj)?.tree;
+        obj = git::Object::load_in(git_dir, &tree)?;
+    }
     let tree = git::Tree::try_from(obj)?;
     if name_only {
         for node in tree.nodes {
             println!("{}", node.name);
         }
     } else {
-        print!("{tree:?}");
+        print!("{tree}");
     }
     Ok(())
 }
 
-fn write_tree(path: &str) -> anyhow::Result<[u8; 20]> {
-    let mut buf: Vec<u8> = Vec::new();
-    let mut entries: Vec<fs::DirEntry> = fs::read_dir(path)
-        .context(format!("failed to read dir {path}"))?
-        .filter_map(|e| e.ok())
-        .collect();
-    entries.sort_by_key(|e| e.file_name());
+/// stages the given files, or every non-ignored file below the given directories.
+/// paths are relative to the current directory, which may be below the repository root.
+fn add(paths: &[String]) -> anyhow::Result<()> {
+    /// `rel` is relative to the repository `root`, empty for the root itself
+    fn stage(
+        index: &mut git::Index,
+        root: &Path,
+        rel: &Path,
+        ignore: &git::IgnoreRules,
+        attributes: &git::Attributes,
+    ) -> anyhow::Result<()> {
+        let path = &root.join(rel);
+        let metadata = fs::symlink_metadata(path)
+            .context(format!("pathspec {rel:?} did not match any files"))?;
+        if metadata.is_dir() {
+            let ignore = ignore.with_dir(path, rel)?;
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let rel = rel.join(&name);
+                if name == ".git" || ignore.is_ignored(&rel, entry.file_type()?.is_dir()) {
+                    continue;
+                }
+                stage(index, root, &rel, &ignore, attributes)?;
+            }
+            return Ok(());
+        }
 
-    for entry in entries {
-        let metadata = entry.metadata()?;
-        let name = entry.file_name();
-        if name == ".git" {
-            continue;
+        let blob = match metadata.is_symlink() {
+            true => git::Object::new_blob_from_symlink(path)?,
+            false => git::Object::new_blob_from_file(path, attributes.text(rel))?,
+        };
+        let hash = blob.persist()?;
+        let name = rel
+            .to_str()
+            .ok_or(anyhow!("path {rel:?} is not valid utf-8"))?;
+        index.add(git::IndexEntry::from_file(path, name, hash)?);
+        Ok(())
+    }
+
+    let root = git::repo::find_repo_root()?;
+    let attributes = git::Attributes::load(&root)?;
+    let index_path = git::repo::git_dir().join("index");
+    let mut index = git::Index::load(&index_path)?;
+    for path in paths {
+        let rel = git::repo::relative_to_root(&root, Path::new(path))?;
+        // collect the ignore rules of the directories above the path
+        let mut ignore = git::IgnoreRules::new();
+        for ancestor in rel
+            .ancestors()
+            .skip(1)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+        {
+            ignore = ignore.with_dir(&root.join(ancestor), ancestor)?;
         }
+        stage(&mut index, &root, &rel, &ignore, &attributes)?;
+    }
+    index.persist(&index_path)
+}
 
-        let (mode, hash) = if metadata.is_dir() {
-            (
-                0o40000,
-                write_tree(entry.path().to_str().unwrap_or_default())?,
-            )
-        } else {
-            let mode = match metadata.is_file() {
-                true => metadata.mode(),
-                false => 0o120_000,
-            };
-            (
-                mode,
-                hash_object(true, entry.path().to_str().unwrap_or_default())?,
-            )
+/// writes the tree of the staged entries, or of the working directory if nothing
+/// was ever staged
+fn write_tree(root: &Path, prefix: &Path) -> anyhow::Result<[u8; 20]> {
+    anyhow::ensure!(
+        prefix
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_))),
+        "prefix {prefix:?} must be a path inside the repository"
+    );
+    let index_path = git::repo::git_dir().join("index");
+    if index_path.exists() {
+        let index = git::Index::load(&index_path)?;
+        if prefix.as_os_str().is_empty() {
+            return write_tree_from_index(&index.entries, 0);
+        }
+        let dir_prefix = format!("{}/", prefix.to_string_lossy().trim_end_matches('/'));
+        let start = index.entries.partition_point(|e| e.path < dir_prefix);
+        let n = index.entries[start..]
+            .iter()
+            .take_while(|e| e.path.starts_with(&dir_prefix))
+            .count();
+        anyhow::ensure!(n > 0, "prefix {prefix:?} is not a directory in the index");
+        return write_tree_from_index(&index.entries[start..start + n], dir_prefix.len());
+    }
+    write_tree_from_dir(root, prefix)
+}
+
+/// writes the tree of `entries`, all sharing the first `prefix_len` bytes of their path
+fn write_tree_from_index(
+    entries: &[git::IndexEntry],
+    prefix_len: usize,
+) -> anyhow::Result<[u8; 20]> {
+    let mut builder = git::TreeBuilder::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = &entries[i];
+        let (mode, name, hash) = match entry.path[prefix_len..].split_once('/') {
+            Some((dir, _)) => {
+                let dir_prefix = format!("{}{dir}/", &entry.path[..prefix_len]);
+                let n = entries[i..]
+                    .iter()
+                    .take_while(|e| e.path.starts_with(&dir_prefix))
+                    .count();
+                let hash = write_tree_from_index(&entries[i..i + n], dir_prefix.len())?;
+                i += n;
+                (0o40000, dir, hash)
+            }
+            None => {
+                i += 1;
+                (entry.mode, &entry.path[prefix_len..], entry.hash)
+            }
         };
-        buf.extend(format!("{mode:o} ").as_bytes());
-        buf.extend(name.as_encoded_bytes());
-        buf.extend([0u8; 1]);
-        buf.extend(hash);
+        builder.add(mode, name, hash);
     }
-    let tree = git::Object::new(git::ObjectKind::Tree, buf);
+    let tree = builder.build();
     tree.persist()?;
     Ok(tree.hash())
 }
 
+/// writes the tree of the directory `prefix` of the working tree at `root`
+fn write_tree_from_dir(root: &Path, prefix: &Path) -> anyhow::Result<[u8; 20]> {
+    fn write_tree(
+        path: &Path,
+        rel: &Path,
+        ignore: &git::IgnoreRules,
+        attributes: &git::Attributes,
+    ) -> anyhow::Result<[u8; 20]> {
+        let ignore = ignore.with_dir(path, rel)?;
+        let mut builder = git::TreeBuilder::new();
+        for entry in fs::read_dir(path)
+            .context(format!("failed to read dir {path:?}"))?
+            .filter_map(|e| e.ok())
+        {
+            let metadata = entry.metadata()?;
+            let name = entry.file_name();
+            if name == ".git" || ignore.is_ignored(&rel.join(&name), metadata.is_dir()) {
+                continue;
+            }
+
+            let file_type = metadata.file_type();
+            let (mode, hash) = if file_type.is_dir() {
+                (
+                    0o40000,
+                    write_tree(&entry.path(), &rel.join(&name), &ignore, attributes)?,
+                )
+            } else if file_type.is_symlink() {
+                let blob = git::Object::new_blob_from_symlink(entry.path())?;
+                (0o120_000, blob.persist()?)
+            } else if file_type.is_file() {
+                (
+                    git::platform::mode_of(&metadata),
+                    hash_object(
+                        true,
+                        entry.path().to_str().unwrap_or_default(),
+                        attributes.text(&rel.join(&name)),
+                    )?,
+                )
+            } else {
+                // sockets, fifos and the like can't be tracked
+                continue;
+            };
+            builder.add(mode, name.as_encoded_bytes(), hash);
+        }
+        let tree = builder.build();
+        tree.persist()?;
+        Ok(tree.hash())
+    }
+    let dir = root.join(prefix);
+    anyhow::ensure!(dir.is_dir(), "prefix {prefix:?} is not a directory");
+    let attributes = git::Attributes::load(root)?;
+    // the ignore files of the directories above the prefix apply too
+    let mut ignore = git::IgnoreRules::new();
+    for rel in prefix
+        .ancestors()
+        .skip(1)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        ignore = ignore.with_dir(&root.join(rel), rel)?;
+    }
+    write_tree(&dir, prefix, &ignore, &attributes)
+}
+
 fn commit_tree(parent: String, message: String, tree: String) -> anyhow::Result<[u8; 20]> {
-    const AUTHOR_NAME: &str = "ArshiAAkhavan <letmemakenewone@gmail.com>";
-    const COMMITER_NAME: &str = AUTHOR_NAME;
-
-    let start = SystemTime::now();
-    let time_millis = start
-        .duration_since(UNIX_EPOCH)
-        .context("Time went backwards")?
-        .as_millis();
+    let git_dir = &git::repo::git_dir();
+    let parent = git::refs::resolve(git_dir, &parent)?;
+    let tree = git::refs::resolve(git_dir, &tree)?;
+    let config = git::Config::load(git_dir)?;
+    let author = git::Signature::author(&config)?;
+    let committer = git::Signature::committer(&config)?;
+
     let mut content = String::new();
     writeln!(content, "tree {tree}")?;
     writeln!(content, "parent {parent}")?;
-    writeln!(content, "author {AUTHOR_NAME} {time_millis} -0500")?;
-    writeln!(content, "committer {COMMITER_NAME} {time_millis} -0500")?;
-    writeln!(content, "\n{message}")?;
+    writeln!(content, "author {author}")?;
+    writeln!(content, "committer {committer}")?;
+    write!(content, "\n{}", normalize_message(&message))?;
 
     let commit = git::Object::new(git::ObjectKind::Commit, content.as_bytes().to_owned());
     commit.persist()?;
@@ -193,170 +778,1930 @@ fn commit_tree(parent: String, message: String, tree: String) -> anyhow::Result<
     Ok(commit.hash())
 }
 
-pub fn git_clone(url: &str, dst: &Path) -> anyhow::Result<()> {
-    fn git_clone(url: &str, dst: &Path) -> anyhow::Result<()> {
-        std::fs::create_dir_all(dst)?;
-        init(dst)?;
-        let client = reqwest::blocking::Client::new();
-        let refs = fetch_refs(&client, url)?;
-        let head_hash = refs
-            .iter()
-            .find(|(name, _)| name == "HEAD")
-            .map(|(_, hash)| hash)
-            .take()
-            .ok_or(anyhow!("no HEADs in refs"))?
-            .to_owned();
-        let packet = fetch_objects(&client, url, refs)?;
-        build_from_head(&head_hash, dst, &packet)?;
-        for obj in packet.objects.values() {
-            obj.persist_in(dst)?;
-        }
-        Ok(())
-    }
-    match git_clone(url, dst) {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            let _ = std::fs::remove_dir_all(dst);
-            Err(e)
-        }
+/// gives a commit or tag message LF line endings, no blank lines around it and a
+/// single trailing newline, so that the object hashes like one made by git
+fn normalize_message(message: &str) -> String {
+    let message = message.replace("\r\n", "\n");
+    format!("{}\n", message.trim_matches('\n'))
+}
+
+/// creates an annotated tag `name` pointing at `object` and its ref under `refs/tags`
+fn tag(name: &str, object: &str, message: &str) -> anyhow::Result<[u8; 20]> {
+    let git_dir = &git::repo::git_dir();
+    let refname = format!("refs/tags/{name}");
+    anyhow::ensure!(
+        !git::refs::ref_exists(git_dir, &refname)?,
+        "tag '{name}' already exists"
+    );
+    let object = git::refs::resolve(git_dir, object)?;
+    let kind = git::Object::open_in(git_dir, &object)?.kind;
+
+    let tag = git::Tag {
+        object,
+        kind,
+        tag: name.into(),
+        tagger: Some(git::Signature::committer(&git::Config::load(git_dir)?)?),
+        message: normalize_message(message),
     }
+    .to_object();
+    let hash = tag.persist_in(git_dir)?;
+    git::refs::write_ref(git_dir, &refname, &hex::encode(hash))?;
+    Ok(hash)
 }
 
-fn fetch_objects(
-    client: &reqwest::blocking::Client,
-    url: &str,
-    refs: Vec<(String, String)>,
-) -> anyhow::Result<git::Packet> {
-    let mut plb = git::PacketLineBuilder::new();
-    for (_, hash) in refs {
-        plb.want(hash);
+/// points a new branch `name` at the current HEAD commit
+fn create_branch(name: &str, force: bool) -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    let refname = format!("refs/heads/{name}");
+    anyhow::ensure!(
+        force || !git::refs::ref_exists(git_dir, &refname)?,
+        "a branch named '{name}' already exists"
+    );
+    let head = git::refs::read_ref(git_dir, "HEAD").context("not a valid object name: 'HEAD'")?;
+    let old = git::refs::read_ref(git_dir, &refname).ok();
+    git::refs::write_ref(git_dir, &refname, &head)?;
+    let message = "branch: Created from HEAD";
+    git::refs::append_reflog(git_dir, &refname, old.as_deref(), &head, message);
+    Ok(())
+}
+
+/// lists the local branches, marking the one HEAD is on with `*`
+fn list_branches() -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    let current = git::refs::read_symbolic_ref(git_dir, "HEAD")?;
+    if current.is_none() {
+        let head = git::refs::read_ref(git_dir, "HEAD")?;
+        println!("* (HEAD detached at {})", &head[..7]);
+    }
+    for (name, _) in git::refs::list_refs(git_dir)? {
+        let Some(branch) = name.strip_prefix("refs/heads/") else {
+            continue;
+        };
+        let marker = match current.as_deref() == Some(name.as_str()) {
+            true => '*',
+            false => ' ',
+        };
+        println!("{marker} {branch}");
     }
-    let payload = plb.build();
+    Ok(())
+}
 
-    let response = client
-        .post(format!("{url}/git-upload-pack"))
-        .header(
-            reqwest::header::CONTENT_TYPE,
-            "application/x-git-upload-pack-request",
-        )
-        .header(
-            reqwest::header::ACCEPT,
-            "application/x-git-upload-pack-result",
-        )
-        .body(payload.data)
-        .send()?;
+/// prints the names of the tags, sorted, one per line
+fn list_tags() -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    for (name, _) in git::refs::list_refs(git_dir)? {
+        if let Some(tag) = name.strip_prefix("refs/tags/") {
+            println!("{tag}");
+        }
+    }
+    Ok(())
+}
 
-    git::Packet::try_from(response.bytes()?)
+/// lists every ref with the kind of object it points at, like `git for-each-ref`
+fn for_each_ref() -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    for (name, hash) in git::refs::list_refs(git_dir)? {
+        // only the header is needed to tell the kind
+        let kind = git::Object::open_in(git_dir, &hash)?.kind;
+        println!("{hash} {kind}\t{name}");
+    }
+    Ok(())
 }
 
-fn fetch_refs(
-    client: &reqwest::blocking::Client,
-    url: &str,
-) -> anyhow::Result<Vec<(String, String)>> {
-    let url = format!("{url}/info/refs");
-
-    let response = client
-        .get(url)
-        .query(&[("service", "git-upload-pack")])
-        .send()?;
-
-    let body = response.bytes()?;
-    let mut refs = Vec::new();
-    for packet_line in body
-        .into_packet_line_iter()
-        .skip_while(|p| !p.is_empty())
-        .skip(1)
-        .take_while(|p| !p.is_empty())
-    {
-        let pos = packet_line
-            .data
-            .iter()
-            .position(|c| *c == b'\0' || *c == b'\n')
-            .unwrap_or(packet_line.len());
-        let name = str::from_utf8(&packet_line.data[41..pos])?;
-        let hash = str::from_utf8(&packet_line.data[..40])?.into();
-        refs.push((name.into(), hash));
+/// finds the best common ancestor of the commits `a` and `b`, one that isn't an ancestor
+/// of another common ancestor. among several such, the last committed one is picked.
+fn merge_base(git_dir: &Path, a: &str, b: &str) -> anyhow::Result<Option<String>> {
+    // the parents of shallow commits were never fetched, so the history ends there
+    let shallow = git::repo::shallow_commits(git_dir)?;
+    let load = |hash: &str| -> anyhow::Result<git::Commit> {
+        let mut commit = git::Commit::try_from(git::Object::load_in(git_dir, hash)?)?;
+        if shallow.contains(hash) {
+            commit.parents.clear();
+        }
+        Ok(commit)
+    };
+    let ancestors = |heads: Vec<String>| -> anyhow::Result<HashSet<String>> {
+        git::RevWalk::new(git_dir, heads)?
+            .map(|commit| Ok(commit?.0))
+            .collect()
+    };
+
+    let (a, _) = git::refs::peel_to_commit(git_dir, &git::refs::resolve(git_dir, a)?)?;
+    let (b, _) = git::refs::peel_to_commit(git_dir, &git::refs::resolve(git_dir, b)?)?;
+    let of_a = ancestors(vec![a])?;
+    // walks the history of `b`, stopping at the first commits of `a` along every path
+    let mut candidates = Vec::new();
+    let mut queue = VecDeque::from([b]);
+    let mut visited = HashSet::new();
+    while let Some(hash) = queue.pop_front() {
+        if !visited.insert(hash.clone()) {
+            continue;
+        }
+        match of_a.contains(&hash) {
+            true => candidates.push(hash),
+            false => queue.extend(load(&hash)?.parents),
+        }
+    }
+    // a candidate reachable from another one is a worse answer than that one
+    let mut parents = Vec::new();
+    for candidate in &candidates {
+        parents.extend(load(candidate)?.parents);
     }
-    Ok(refs)
+    let redundant = ancestors(parents)?;
+    let mut best = Vec::new();
+    for candidate in candidates.into_iter().filter(|c| !redundant.contains(c)) {
+        best.push((load(&candidate)?.committer.time, candidate));
+    }
+    Ok(best
+        .into_iter()
+        .max_by_key(|(time, _)| *time)
+        .map(|(_, hash)| hash))
 }
 
-fn build_from_head(
-    head_hash: &str,
-    current_dir: &Path,
-    packet: &git::Packet,
-) -> anyhow::Result<()> {
-    build_commit(head_hash, current_dir, packet)
+/// prints the hashes of the commits reachable from `name`, newest first
+fn rev_list(name: &str, max_count: Option<usize>) -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    let head = git::refs::resolve(git_dir, name)?;
+    let walk = git::RevWalk::new(git_dir, [head])?;
+    for commit in walk.take(max_count.unwrap_or(usize::MAX)) {
+        println!("{}", commit?.0);
+    }
+    Ok(())
 }
 
-fn build_commit(hash: &str, current_dir: &Path, packet: &git::Packet) -> anyhow::Result<()> {
-    let obj = packet
-        .objects
-        .get(hex::decode(hash)?.as_slice())
-        .ok_or(anyhow!("failed to find {hash} in packet"))?;
-    for line in obj.body.lines() {
-        let line = line?;
-        let Some((obj_type, hash)) = line.split_once(char::is_whitespace) else {
+/// reports the number of loose objects and the disk space they take, along with the
+/// objects and size of the packs when `verbose`, like `git count-objects`
+fn count_objects(verbose: bool) -> anyhow::Result<()> {
+    let objects = git::repo::git_dir().join("objects");
+    let (mut count, mut size) = (0, 0);
+    // a missing objects directory just means an empty repository
+    for dir in fs::read_dir(&objects).into_iter().flatten() {
+        let dir = dir?;
+        let name = dir.file_name();
+        // skips `pack` and `info`, which hold no loose objects
+        if name.len() != 2 || !name.as_encoded_bytes().iter().all(u8::is_ascii_hexdigit) {
             continue;
-        };
-        match obj_type {
-            "tree" => {
-                build_tree(hash, current_dir, packet)?;
+        }
+        for object in fs::read_dir(dir.path())? {
+            let object = object?;
+            let name = object.file_name();
+            if name.len() != 38 || !name.as_encoded_bytes().iter().all(u8::is_ascii_hexdigit) {
+                continue;
+            }
+            count += 1;
+            size += git::platform::disk_usage(&object.metadata()?);
+        }
+    }
+    if !verbose {
+        println!("{count} objects, {} kilobytes", size / 1024);
+        return Ok(());
+    }
+
+    let (mut in_pack, mut packs, mut size_pack) = (0, 0, 0);
+    for entry in fs::read_dir(objects.join("pack")).into_iter().flatten() {
+        let path = entry?.path();
+        match path.extension().and_then(OsStr::to_str) {
+            Some("idx") => {
+                in_pack += git::PackIndex::load(&path)?.entries.len();
+                size_pack += path.metadata()?.len();
             }
-            "parent" => {
-                build_commit(hash, current_dir, packet)?;
+            Some("pack") => {
+                packs += 1;
+                size_pack += path.metadata()?.len();
             }
             _ => (),
         }
     }
+    println!("count: {count}");
+    println!("size: {}", size / 1024);
+    println!("in-pack: {in_pack}");
+    println!("packs: {packs}");
+    println!("size-pack: {}", size_pack / 1024);
+    Ok(())
+}
 
+/// checks the pack at `path` and, if `verbose`, lists its objects the way
+/// `git verify-pack -v` does
+fn verify_pack(verbose: bool, path: &str) -> anyhow::Result<()> {
+    let file = fs::File::open(path).context(format!("failed to open the pack {path}"))?;
+    let packet = git::Packet::from_reader(file)?;
+    if verbose {
+        // number of deltified objects per chain length
+        let mut chains: Vec<usize> = Vec::new();
+        for entry in &packet.entries {
+            print!(
+                "{} {:<6} {} {} {}",
+                hex::encode(entry.hash),
+                entry.kind.to_string(),
+                entry.size,
+                entry.packed_size,
+                entry.offset
+            );
+            match entry.base {
+                Some(base) => {
+                    println!(" {} {}", entry.depth, hex::encode(base));
+                    if chains.len() < entry.depth {
+                        chains.resize(entry.depth, 0);
+                    }
+                    chains[entry.depth - 1] += 1;
+                }
+                None => println!(),
+            }
+        }
+        let non_delta = packet.entries.iter().filter(|e| e.base.is_none()).count();
+        println!("non delta: {non_delta} objects");
+        for (depth, count) in chains.iter().enumerate().filter(|(_, c)| **c > 0) {
+            let noun = if *count == 1 { "object" } else { "objects" };
+            println!("chain length = {}: {count} {noun}", depth + 1);
+        }
+    }
+    println!("{path}: ok");
     Ok(())
 }
 
-fn build_tree(hash: &str, current_dir: &Path, packet: &git::Packet) -> anyhow::Result<()> {
-    eprintln!("fetching tree: {hash}");
+/// writes the `.idx` for the pack at `pack` next to it, returning the pack checksum
+fn index_pack(pack: &Path) -> anyhow::Result<[u8; 20]> {
+    anyhow::ensure!(
+        pack.extension() == Some(OsStr::new("pack")),
+        "packfile name {pack:?} does not end with '.pack'"
+    );
+    let file = fs::File::open(pack).context(format!("failed to open the pack {pack:?}"))?;
+    let packet = git::Packet::from_reader(file)?;
+    let index = git::PackIndex::from_packet(&packet);
+    index.persist(&pack.with_extension("idx"))?;
+    Ok(index.pack_checksum)
+}
 
-    let obj = packet
-        .objects
-        .get(hex::decode(hash)?.as_slice())
-        .ok_or(anyhow!("failed to find {hash} in packet"))?;
-    let obj = git::Object::clone(obj);
-    let tree = git::Tree::try_from(obj)?;
-    for node in tree.nodes {
-        match node.kind {
-            git::NodeKind::Dir { .. } => {
-                let dir_path = current_dir.join(&node.name);
-                std::fs::create_dir_all(&dir_path).context(format!(
-                    "failed to create a directory for tree {}",
-                    node.name
-                ))?;
-                build_tree(&hex::encode(&node.hash), &dir_path, packet)?;
+/// writes every object of the pack at `pack` loose into `git_dir`. bases of thin pack
+/// deltas are looked up among the objects already there.
+fn unpack_objects(git_dir: &Path, pack: &Path) -> anyhow::Result<()> {
+    let raw = fs::read(pack).context(format!("failed to read the pack {pack:?}"))?;
+    let packet = git::Packet::from_bytes_with_bases(&raw, git::Verbosity::Normal, |base| {
+        git::Object::load_in(git_dir, &hex::encode(base)).ok()
+    })?;
+    persist_objects(git_dir, &packet)
+}
+
+/// prints the refs advertised by the remote at `url`
+fn ls_remote(url: &str) -> anyhow::Result<()> {
+    let (refs, _) = connect(url, &git::FetchOptions::default())?.advertise_refs()?;
+    for r in refs {
+        println!("{}\t{}", r.hash, r.name);
+        if let Some(peeled) = r.peeled {
+            println!("{peeled}\t{}^{{}}", r.name);
+        }
+    }
+    Ok(())
+}
+
+/// brings the objects of the remote branches and tags at `url` into `git_dir`, telling
+/// the remote which objects are already there so they aren't sent again, and updates
+/// the remote-tracking branches and tags
+fn fetch(git_dir: &Path, url: &str) -> anyhow::Result<()> {
+    let mut shallow: Vec<String> = git::repo::shallow_commits(git_dir)?.into_iter().collect();
+    shallow.sort();
+    let options = git::FetchOptions {
+        shallow,
+        ..Default::default()
+    };
+    fetch_from(git_dir, connect(url, &options)?.as_ref(), &options)
+}
+
+/// fetches into `git_dir` from the remote behind `transport`, connected with `options`,
+/// see `fetch`
+fn fetch_from(
+    git_dir: &Path,
+    transport: &dyn git::Transport,
+    options: &git::FetchOptions,
+) -> anyhow::Result<()> {
+    let (refs, capabilities) = transport.advertise_refs()?;
+    let refs: Vec<_> = refs
+        .into_iter()
+        .filter(|r| r.name.starts_with("refs/heads/") || r.name.starts_with("refs/tags/"))
+        .collect();
+
+    let present = |hash: &str| git::Object::open_in(git_dir, hash).is_ok();
+    let mut haves: Vec<String> = git::refs::list_refs(git_dir)?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .filter(|hash| present(hash))
+        .collect();
+    haves.sort();
+    haves.dedup();
+    let wants: Vec<_> = refs.iter().filter(|r| !present(&r.hash)).cloned().collect();
+    if !wants.is_empty() {
+        let (pack, update) = fetch_objects(transport, wants, &capabilities, options, &haves)?;
+        let packet = receive_pack(git_dir, pack, git::Verbosity::Normal)?;
+        persist_objects(git_dir, &packet)?;
+        // the remote may have cut the new history short, or filled in the one behind
+        // commits that were shallow
+        if !update.shallow.is_empty() || !update.unshallow.is_empty() {
+            let mut shallow = git::repo::shallow_commits(git_dir)?;
+            shallow.extend(update.shallow);
+            for hex in &update.unshallow {
+                shallow.remove(hex);
             }
-            git::NodeKind::File { .. } | git::NodeKind::SymLink { .. } => {
-                build_file(&node, current_dir, packet)?;
+            git::repo::write_shallow_commits(git_dir, &shallow)?;
+        }
+    }
+
+    let mut header = Some(format!("From {}", transport.url()));
+    let mut rejected = false;
+    for r in &refs {
+        let (local, kind) = match r.name.strip_prefix("refs/heads/") {
+            Some(branch) => (format!("refs/remotes/origin/{branch}"), "branch"),
+            None => (r.name.clone(), "tag"),
+        };
+        let short = |name: &str| {
+            let name = name.strip_prefix("refs/remotes/").unwrap_or(name);
+            name.strip_prefix("refs/tags/")
+                .or(name.strip_prefix("refs/heads/"))
+                .unwrap_or(name)
+                .to_owned()
+        };
+        let old = git::refs::read_ref(git_dir, &local).ok();
+        let (summary, reason) = match old.as_deref() {
+            Some(old) if old == r.hash => continue,
+            // tags aren't expected to move, so one that did is left alone like git
+            // does without --force
+            Some(_) if kind == "tag" => {
+                if let Some(header) = header.take() {
+                    eprintln!("{header}");
+                }
+                let name = short(&r.name);
+                let summary = " ! [rejected]";
+                eprintln!("{summary:<19}  {name:<10} -> {name}  (would clobber existing tag)");
+                rejected = true;
+                continue;
+            }
+            Some(old) => {
+                let base = merge_base(git_dir, old, &r.hash).ok().flatten();
+                let reason = match base.as_deref() == Some(old) {
+                    true => "fast-forward",
+                    false => "forced-update",
+                };
+                (format!("   {}..{}", &old[..7], &r.hash[..7]), reason)
             }
+            None => (format!(" * [new {kind}]"), "storing head"),
+        };
+        git::refs::write_ref(git_dir, &local, &r.hash)?;
+        let message = format!("fetch {}: {reason}", transport.url());
+        git::refs::append_reflog(git_dir, &local, old.as_deref(), &r.hash, &message);
+        if let Some(header) = header.take() {
+            eprintln!("{header}");
         }
+        eprintln!("{summary:<19}  {:<10} -> {}", short(&r.name), short(&local));
     }
+    anyhow::ensure!(!rejected, "some local refs could not be updated");
     Ok(())
 }
 
-fn build_file(node: &git::Node, current_dir: &Path, packet: &git::Packet) -> anyhow::Result<()> {
-    let file_path = current_dir.join(&node.name);
-    if file_path.exists() {
-        return Ok(());
+#[derive(Default)]
+pub struct CloneOptions {
+    /// number of commits to fetch from the tip, fetches the whole history if `None`
+    depth: Option<u32>,
+    /// branch to check out instead of the remote HEAD
+    branch: Option<String>,
+    /// mirror the repository without a working tree
+    bare: bool,
+    /// keep the fetched pack as is instead of writing every object loose
+    keep_pack: bool,
+    verbosity: git::Verbosity,
+}
+
+impl CloneOptions {
+    /// what to ask of the remote besides the objects
+    fn fetch_options(&self) -> git::FetchOptions {
+        git::FetchOptions {
+            depth: self.depth,
+            quiet: self.verbosity == git::Verbosity::Quiet,
+            shallow: Vec::new(),
+        }
     }
-    eprintln!("fetching file: {} [{}]", node.name, hex::encode(&node.hash));
+}
 
-    let hash = hex::encode(&node.hash);
-    let obj = packet
-        .objects
-        .get(hex::decode(&hash)?.as_slice())
-        .ok_or(anyhow!("failed to find {hash} in packet"))?;
+/// materializes the tree of a commit (or a tree itself) from the object store into
+/// the working directory. files already present in the working directory are kept.
+fn checkout(hash: &str, git_dir: &Path, repo: &Path) -> anyhow::Result<()> {
+    let obj = git::Object::load_in(git_dir, hash)?;
+    let tree_hash = match git::Commit::try_from(obj) {
+        Ok(commit) => commit.tree,
+        // not a commit, materialize it as a tree
+        Err(_) => hash.to_owned(),
+    };
+    build_tree(
+        &tree_hash,
+        git_dir,
+        repo,
+        &mut HashSet::new(),
+        &mut git::Progress::quiet(),
+    )
+}
 
-    // create file with correct permissions
-    std::fs::File::create(&file_path)?;
-    let permissions = PermissionsExt::from_mode(node.kind.mode() % (1 << 9));
-    std::fs::set_permissions(&file_path, permissions)?;
+/// lists the files of the working directory that differ from the commit at HEAD
+fn status(git_dir: &Path, repo: &Path) -> anyhow::Result<()> {
+    if let Some(branch) = git::refs::read_symbolic_ref(git_dir, "HEAD")? {
+        println!(
+            "On branch {}",
+            branch.strip_prefix("refs/heads/").unwrap_or(&branch)
+        );
+    }
+
+    let mut head = HashMap::new();
+    // a repository without commits compares against an empty tree
+    if let Ok(hash) = git::refs::read_ref(git_dir, "HEAD") {
+        let commit = git::Commit::try_from(git::Object::load_in(git_dir, &hash)?)?;
+        tree_to_map(git_dir, &commit.tree, Path::new(""), &mut head)?;
+    }
+    let mut worktree = HashMap::new();
+    let attributes = git::Attributes::load(repo)?;
+    worktree_to_map(
+        repo,
+        Path::new(""),
+        &git::IgnoreRules::new(),
+        &attributes,
+        &mut worktree,
+    )?;
+
+    let mut modified: Vec<&PathBuf> = Vec::new();
+    let mut deleted: Vec<&PathBuf> = Vec::new();
+    for (path, hash) in &head {
+        match worktree.get(path) {
+            Some(current) if current != hash => modified.push(path),
+            Some(_) => (),
+            None => deleted.push(path),
+        }
+    }
+    let mut added: Vec<&PathBuf> = worktree.keys().filter(|p| !head.contains_key(*p)).collect();
+    modified.sort();
+    deleted.sort();
+    added.sort();
+
+    if modified.is_empty() && deleted.is_empty() && added.is_empty() {
+        println!("nothing to commit, working tree clean");
+        return Ok(());
+    }
+    if !modified.is_empty() || !deleted.is_empty() {
+        println!("Changes not staged for commit:");
+        for path in modified {
+            println!("\tmodified:   {}", path.display());
+        }
+        for path in deleted {
+            println!("\tdeleted:    {}", path.display());
+        }
+        println!();
+    }
+    if !added.is_empty() {
+        println!("Untracked files:");
+        for path in added {
+            println!("\t{}", path.display());
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// lists the paths that differ between two trees, or the trees of two commits, like
+/// `git diff-tree -r --name-status`
+fn diff_tree(old: &str, new: &str, git_dir: &Path) -> anyhow::Result<()> {
+    let tree_of = |name: &str| -> anyhow::Result<String> {
+        let hash = git::refs::resolve(git_dir, name)?;
+        let obj = git::Object::load_in(git_dir, &hash)?;
+        Ok(match git::Commit::try_from(obj) {
+            Ok(commit) => commit.tree,
+            Err(_) => hash,
+        })
+    };
+    for (status, path) in tree_changes(git_dir, Some(&tree_of(old)?), &tree_of(new)?)? {
+        println!("{status}\t{}", path.display());
+    }
+    Ok(())
+}
+
+/// the `A`dded, `D`eleted and `M`odified blobs going from the tree `old`, or from
+/// nothing, to the tree `new`, in path order
+fn tree_changes(
+    git_dir: &Path,
+    old: Option<&str>,
+    new: &str,
+) -> anyhow::Result<Vec<(char, PathBuf)>> {
+    let mut old_map = HashMap::new();
+    if let Some(old) = old {
+        tree_to_map(git_dir, old, Path::new(""), &mut old_map)?;
+    }
+    let mut new_map = HashMap::new();
+    tree_to_map(git_dir, new, Path::new(""), &mut new_map)?;
+
+    let mut changes: Vec<(char, PathBuf)> = Vec::new();
+    for (path, hash) in &old_map {
+        match new_map.get(path) {
+            Some(current) if current != hash => changes.push(('M', path.clone())),
+            Some(_) => (),
+            None => changes.push(('D', path.clone())),
+        }
+    }
+    for path in new_map.keys().filter(|p| !old_map.contains_key(*p)) {
+        changes.push(('A', path.clone()));
+    }
+    changes.sort_by(|(_, a), (_, b)| {
+        a.as_os_str()
+            .as_encoded_bytes()
+            .cmp(b.as_os_str().as_encoded_bytes())
+    });
+    Ok(changes)
+}
+
+/// shows the object `name` resolves to: a commit along with the paths it changed since
+/// its first parent, a tag followed by what it points at, the entries of a tree or the
+/// content of a blob
+fn show(git_dir: &Path, name: &str) -> anyhow::Result<()> {
+    let hash = git::refs::resolve(git_dir, name)?;
+    let obj = git::Object::load_in(git_dir, &hash)?;
+    match obj.kind() {
+        git::ObjectKind::Commit => {
+            use std::io::Write;
+
+            // printed as stored, as the parsed commit leaves out headers like `gpgsig`
+            let body = obj.body.clone();
+            let commit = git::Commit::try_from(obj)?;
+            println!("commit {hash}");
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&body)?;
+            writeln!(stdout)?;
+            let parent = match commit.parents.first() {
+                Some(parent) => Some(git::Commit::try_from(git::Object::load_in(
+                    git_dir, parent,
+                )?)?),
+                None => None,
+            };
+            let old = parent.as_ref().map(|p| p.tree.as_str());
+            for (status, path) in tree_changes(git_dir, old, &commit.tree)? {
+                println!("{status}\t{}", path.display());
+            }
+        }
+        git::ObjectKind::Tag => {
+            let tag = git::Tag::try_from(obj)?;
+            println!("{tag}");
+            show(git_dir, &tag.object)?;
+        }
+        git::ObjectKind::Tree => {
+            println!("tree {name}\n");
+            for node in git::Tree::try_from(obj)?.nodes {
+                match node.kind {
+                    git::NodeKind::Dir { .. } => println!("{}/", node.name),
+                    _ => println!("{}", node.name),
+                }
+            }
+        }
+        git::ObjectKind::Blob => {
+            use std::io::Write;
+            std::io::stdout().lock().write_all(&obj.body)?;
+        }
+    }
+    Ok(())
+}
+
+/// collects the blobs reachable from the tree `hash`, keyed by their path below `prefix`
+fn tree_to_map(
+    git_dir: &Path,
+    hash: &str,
+    prefix: &Path,
+    map: &mut HashMap<PathBuf, [u8; 20]>,
+) -> anyhow::Result<()> {
+    let tree = git::Tree::try_from(git::Object::load_in(git_dir, hash)?)?;
+    for node in tree.nodes {
+        let path = prefix.join(&node.name);
+        match node.kind {
+            git::NodeKind::Dir { .. } => tree_to_map(git_dir, &hex::encode(node.hash), &path, map)?,
+            git::NodeKind::File { .. } | git::NodeKind::SymLink { .. } => {
+                map.insert(path, node.hash);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// hashes the non-ignored files of the working directory below `rel` as blobs,
+/// without writing them to the object store
+fn worktree_to_map(
+    repo: &Path,
+    rel: &Path,
+    ignore: &git::IgnoreRules,
+    attributes: &git::Attributes,
+    map: &mut HashMap<PathBuf, [u8; 20]>,
+) -> anyhow::Result<()> {
+    let dir = repo.join(rel);
+    let ignore = ignore.with_dir(&dir, rel)?;
+    for entry in fs::read_dir(&dir).context(format!("failed to read dir {dir:?}"))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let path = rel.join(&name);
+        let file_type = entry.file_type()?;
+        if name == ".git" || ignore.is_ignored(&path, file_type.is_dir()) {
+            continue;
+        }
+        if file_type.is_dir() {
+            worktree_to_map(repo, &path, &ignore, attributes, map)?;
+            continue;
+        }
+        let blob = match file_type.is_symlink() {
+            true => git::Object::new_blob_from_symlink(entry.path())?,
+            false => git::Object::new_blob_from_file(entry.path(), attributes.text(&path))?,
+        };
+        map.insert(path, blob.hash());
+    }
+    Ok(())
+}
+
+pub fn git_clone(url: &str, dst: &Path, options: &CloneOptions) -> anyhow::Result<()> {
+    match local_source(url) {
+        Some(source) => clone_into(dst, options, |git_dir| {
+            clone_local(&source, git_dir, options)
+        }),
+        None => clone_into(dst, options, |git_dir| {
+            let transport = connect(url, &options.fetch_options())?;
+            clone_remote(transport.as_ref(), git_dir, options)
+        }),
+    }
+}
+
+/// sets up a new repository at `dst` with what `fetch` brings into its git directory,
+/// and checks out its head. `dst` is removed if any of it fails.
+fn clone_into<F>(dst: &Path, options: &CloneOptions, fetch: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&Path) -> anyhow::Result<Cloned>,
+{
+    fn clone_into<F>(dst: &Path, options: &CloneOptions, fetch: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Path) -> anyhow::Result<Cloned>,
+    {
+        std::fs::create_dir_all(dst)?;
+        init(dst, options.bare)?;
+        let git_dir = git_dir_of(dst, options.bare);
+        let cloned = fetch(&git_dir)?;
+        let mut config = git::Config::load(&git_dir)?;
+        config.set("remote.origin.url", &cloned.url)?;
+        // a bare clone mirrors the remote branches as its own, so it tracks none
+        if !options.bare {
+            config.add("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+        }
+        config.persist(&git_dir)?;
+        let Some((head_hash, branch)) = cloned.head else {
+            eprintln!("warning: You appear to have cloned an empty repository.");
+            return Ok(());
+        };
+        if !options.bare {
+            let mut progress = git::Progress::new("Updating files", None, options.verbosity);
+            build_from_head(&head_hash, &git_dir, dst, &mut progress)?;
+            progress.finish();
+        }
+        let boundary = cloned.shallow.iter().cloned().collect();
+        git::repo::write_shallow_commits(&git_dir, &boundary)?;
+        write_clone_refs(
+            &git_dir,
+            &cloned.url,
+            &cloned.refs,
+            &branch,
+            &head_hash,
+            options.bare,
+        )?;
+        if !options.bare {
+            config.set(&format!("branch.{branch}.remote"), "origin")?;
+            config.set(
+                &format!("branch.{branch}.merge"),
+                &format!("refs/heads/{branch}"),
+            )?;
+            config.persist(&git_dir)?;
+        }
+        Ok(())
+    }
+    // the destination is removed if the clone fails, so it must not hold anything yet
+    anyhow::ensure!(
+        !dst.exists() || dst.read_dir()?.next().is_none(),
+        "destination path '{}' already exists and is not an empty directory",
+        dst.display()
+    );
+    match clone_into(dst, options, fetch) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(dst);
+            Err(e)
+        }
+    }
+}
+
+/// what a clone brought into the new repository, before its refs and working tree
+/// are set up
+struct Cloned {
+    /// url of the remote, as recorded in the config
+    url: String,
+    refs: Vec<git::refs::Ref>,
+    /// hash and name of the branch to check out, `None` for an empty repository
+    head: Option<(String, String)>,
+    /// commits whose parents were not fetched
+    shallow: Vec<String>,
+}
+
+/// fetches the objects of every ref from the remote
+/// behind `transport` into `git_dir`. any `Transport` will do, so an in-memory one can
+/// stand in for a server.
+fn clone_remote(
+    transport: &dyn git::Transport,
+    git_dir: &Path,
+    options: &CloneOptions,
+) -> anyhow::Result<Cloned> {
+    let (refs, capabilities) = transport.advertise_refs()?;
+    let url = transport.url().to_owned();
+    let Some((head_hash, branch)) = clone_head(&refs, &capabilities, options)? else {
+        return Ok(Cloned {
+            url,
+            refs,
+            head: None,
+            shallow: Vec::new(),
+        });
+    };
+    // every ref is written into the clone, so the objects of all of them are wanted
+    // even when checking out another branch than the remote's HEAD
+    let (pack, shallow) = fetch_objects(
+        transport,
+        refs.clone(),
+        &capabilities,
+        &options.fetch_options(),
+        &[],
+    )?;
+    match options.keep_pack {
+        true => {
+            let checksum = keep_pack(git_dir, pack, options.verbosity)?;
+            if options.verbosity != git::Verbosity::Quiet {
+                eprintln!("Kept pack {}", hex::encode(checksum));
+            }
+        }
+        false => persist_objects(git_dir, &receive_pack(git_dir, pack, options.verbosity)?)?,
+    }
+    Ok(Cloned {
+        url,
+        refs,
+        head: Some((head_hash, branch)),
+        shallow: shallow.shallow,
+    })
+}
+
+/// picks the transport for `url` by its scheme, to fetch with `options`
+fn connect(url: &str, options: &git::FetchOptions) -> anyhow::Result<Box<dyn git::Transport>> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Ok(Box::new(git::HttpTransport::new(url, options)?));
+    }
+    match git::SshTarget::parse(url)? {
+        Some(target) => Ok(Box::new(git::SshTransport::connect(url, &target, options)?)),
+        None => anyhow::bail!("unsupported remote url, expected http(s) or ssh"),
+    }
+}
+
+/// the path a `file://` url or a plain path points at, `None` for the urls of remote
+/// transports. like git, `host:path` with a colon before any slash is not a path,
+/// unless it is a drive letter as in `C:\repo`.
+fn local_source(url: &str) -> Option<PathBuf> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Some(PathBuf::from(path));
+    }
+    if url.contains("://") {
+        return None;
+    }
+    if let [drive, b':', b'\\' | b'/', ..] = url.as_bytes() {
+        if drive.is_ascii_alphabetic() {
+            return Some(PathBuf::from(url));
+        }
+    }
+    match (url.find(':'), url.find('/')) {
+        (Some(colon), Some(slash)) if colon < slash => None,
+        (Some(_), None) => None,
+        _ => Some(PathBuf::from(url)),
+    }
+}
+
+/// clones the repository at `source` on this machine by copying its objects into
+/// `git_dir` and reading its refs directly
+fn clone_local(source: &Path, git_dir: &Path, options: &CloneOptions) -> anyhow::Result<Cloned> {
+    let source_git_dir = match source.join(".git").is_dir() {
+        true => source.join(".git"),
+        false => source.to_owned(),
+    };
+    anyhow::ensure!(
+        source_git_dir.join("objects").is_dir() && source_git_dir.join("HEAD").is_file(),
+        "repository '{}' does not exist",
+        source.display()
+    );
+    if options.depth.is_some() {
+        eprintln!("warning: --depth is ignored in local clones");
+    }
+
+    let mut refs: Vec<git::refs::Ref> = git::refs::list_refs(&source_git_dir)?
+        .into_iter()
+        .map(|(name, hash)| git::refs::Ref {
+            name,
+            hash,
+            peeled: None,
+            symref_target: None,
+        })
+        .collect();
+    // HEAD of a repository without commits points at a branch that doesn't exist yet
+    if let Ok(hash) = git::refs::read_ref(&source_git_dir, "HEAD") {
+        refs.insert(
+            0,
+            git::refs::Ref {
+                name: "HEAD".into(),
+                hash,
+                peeled: None,
+                symref_target: git::refs::read_symbolic_ref(&source_git_dir, "HEAD")?,
+            },
+        );
+    }
+    copy_objects(&source_git_dir, git_dir)?;
+    let shallow = match fs::read_to_string(source_git_dir.join("shallow")) {
+        Ok(content) => content.lines().map(String::from).collect(),
+        Err(_) => Vec::new(),
+    };
+    let head = clone_head(&refs, &git::Capabilities::default(), options)?;
+    Ok(Cloned {
+        url: fs::canonicalize(source)?.to_string_lossy().into_owned(),
+        refs,
+        head,
+        shallow,
+    })
+}
+
+/// copies the loose objects and the packs of `source_git_dir` into `git_dir`, and
+/// points the clone at the alternates of the source so the objects the source borrows
+/// stay reachable
+fn copy_objects(source_git_dir: &Path, git_dir: &Path) -> anyhow::Result<()> {
+    let source = source_git_dir.join("objects");
+    let objects = git_dir.join("objects");
+    for dir in fs::read_dir(&source).context(format!("failed to read {source:?}"))? {
+        let dir = dir?;
+        // `info` is handled below, only its alternates carry over
+        if dir.file_name() == "info" || !dir.file_type()?.is_dir() {
+            continue;
+        }
+        let target = objects.join(dir.file_name());
+        fs::create_dir_all(&target)?;
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            fs::copy(file.path(), target.join(file.file_name()))
+                .context(format!("failed to copy {:?}", file.path()))?;
+        }
+    }
+
+    let alternates = match fs::read_to_string(source.join("info").join("alternates")) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context("failed to read the alternates of the source"),
+    };
+    // relative entries are relative to the source's object directory, which the clone
+    // doesn't share, so they are written out absolute
+    let mut content = String::new();
+    for line in alternates.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let dir = source.join(line);
+        let dir = fs::canonicalize(&dir).unwrap_or(dir);
+        content.push_str(&format!("{}\n", dir.display()));
+    }
+    fs::create_dir_all(objects.join("info"))?;
+    fs::write(objects.join("info").join("alternates"), content)?;
+    Ok(())
+}
+
+/// picks the commit and branch a clone checks out: the requested branch or else the
+/// one the remote HEAD points at. `None` if the remote has no commits at all.
+fn clone_head(
+    refs: &[git::refs::Ref],
+    capabilities: &git::Capabilities,
+    options: &CloneOptions,
+) -> anyhow::Result<Option<(String, String)>> {
+    if refs.is_empty() && options.branch.is_none() {
+        return Ok(None);
+    }
+    let head_name = match &options.branch {
+        Some(branch) => format!("refs/heads/{branch}"),
+        None => "HEAD".into(),
+    };
+    let head_hash = refs
+        .iter()
+        .find(|r| r.name == head_name)
+        .map(|r| &r.hash)
+        .ok_or(match &options.branch {
+            Some(branch) => anyhow!("remote branch {branch} not found in upstream origin"),
+            None => anyhow!("no HEADs in refs"),
+        })?
+        .to_owned();
+    let branch = match &options.branch {
+        Some(branch) => branch.clone(),
+        None => remote_head_branch(refs, capabilities, &head_hash),
+    };
+    Ok(Some((head_hash, branch)))
+}
+
+/// records the remote `name` in the config, fetching its branches into
+/// `refs/remotes/<name>`
+fn remote_add(name: &str, url: &str) -> anyhow::Result<()> {
+    let git_dir = &git::repo::git_dir();
+    let mut config = git::Config::load(git_dir)?;
+    anyhow::ensure!(
+        !name.is_empty() && !name.contains(char::is_whitespace),
+        "'{name}' is not a valid remote name"
+    );
+    if config.subsections("remote").contains(&name) {
+        anyhow::bail!("remote {name} already exists.");
+    }
+    config.set(&format!("remote.{name}.url"), url)?;
+    config.add(
+        &format!("remote.{name}.fetch"),
+        &format!("+refs/heads/*:refs/remotes/{name}/*"),
+    )?;
+    config.persist(git_dir)
+}
+
+/// prints the name of every remote, followed by its fetch and push urls when `verbose`
+fn list_remotes(verbose: bool) -> anyhow::Result<()> {
+    let config = git::Config::load(&git::repo::git_dir())?;
+    let mut remotes = config.subsections("remote");
+    remotes.sort();
+    for name in remotes {
+        if !verbose {
+            println!("{name}");
+            continue;
+        }
+        let url = config
+            .get(&format!("remote.{name}.url"))
+            .unwrap_or_default();
+        let push_url = config.get(&format!("remote.{name}.pushurl")).unwrap_or(url);
+        println!("{name}\t{url} (fetch)");
+        println!("{name}\t{push_url} (push)");
+    }
+    Ok(())
+}
+
+/// name of the branch the remote HEAD points at, as told by its v2 `symref-target`, the
+/// `symref` capability or guessed from the advertised branches sharing HEAD's hash
+fn remote_head_branch(
+    refs: &[git::refs::Ref],
+    capabilities: &git::Capabilities,
+    head_hash: &str,
+) -> String {
+    if let Some(branch) = refs
+        .iter()
+        .find(|r| r.name == "HEAD")
+        .and_then(|r| r.symref_target.as_deref()?.strip_prefix("refs/heads/"))
+    {
+        return branch.into();
+    }
+    if let Some(branch) = capabilities
+        .get("symref")
+        .and_then(|symref| symref.strip_prefix("HEAD:refs/heads/"))
+    {
+        return branch.into();
+    }
+    let candidates: Vec<&str> = refs
+        .iter()
+        .filter(|r| r.hash == head_hash)
+        .filter_map(|r| r.name.strip_prefix("refs/heads/"))
+        .collect();
+    ["master", "main"]
+        .into_iter()
+        .find(|name| candidates.contains(name))
+        .or(candidates.first().copied())
+        .unwrap_or("master")
+        .into()
+}
+
+/// points HEAD at the checked out branch and mirrors the advertised refs locally.
+/// a bare clone keeps the remote branches as its own instead of under `refs/remotes`.
+fn write_clone_refs(
+    git_dir: &Path,
+    url: &str,
+    refs: &[git::refs::Ref],
+    branch: &str,
+    head_hash: &str,
+    bare: bool,
+) -> anyhow::Result<()> {
+    let message = format!("clone: from {url}");
+    let head = format!("refs/heads/{branch}");
+    git::refs::write_ref(git_dir, &head, head_hash)?;
+    git::refs::write_symbolic_ref(git_dir, "HEAD", &head)?;
+    git::refs::append_reflog(git_dir, &head, None, head_hash, &message);
+    git::refs::append_reflog(git_dir, "HEAD", None, head_hash, &message);
+
+    for git::refs::Ref { name, hash, .. } in refs {
+        if let Some(remote_branch) = name.strip_prefix("refs/heads/") {
+            match bare {
+                true => git::refs::write_ref(git_dir, name, hash)?,
+                false => {
+                    let remote = format!("refs/remotes/origin/{remote_branch}");
+                    git::refs::write_ref(git_dir, &remote, hash)?
+                }
+            }
+        } else if name.starts_with("refs/tags/") {
+            git::refs::write_ref(git_dir, name, hash)?;
+        }
+    }
+    if bare {
+        return Ok(());
+    }
+    git::refs::write_symbolic_ref(
+        git_dir,
+        "refs/remotes/origin/HEAD",
+        &format!("refs/remotes/origin/{branch}"),
+    )?;
+    let origin_head = "refs/remotes/origin/HEAD";
+    git::refs::append_reflog(git_dir, origin_head, None, head_hash, &message);
+    Ok(())
+}
+
+/// asks the remote for the objects of `refs`, telling it about the `haves` already
+/// there. returns a reader of the pack along with the changes to the shallow boundary.
+/// `options` must be the ones `transport` was connected with.
+fn fetch_objects<'a>(
+    transport: &'a dyn git::Transport,
+    refs: Vec<git::refs::Ref>,
+    capabilities: &git::Capabilities,
+    options: &git::FetchOptions,
+    haves: &[String],
+) -> anyhow::Result<(Box<dyn Read + 'a>, git::ShallowUpdate)> {
+    let wants: Vec<String> = refs
+        .into_iter()
+        .filter(|r| r.hash != git::refs::ZERO_ID)
+        .map(|r| r.hash)
+        .collect();
+    anyhow::ensure!(!wants.is_empty(), "no refs to fetch objects for");
+    let body = transport.fetch(&wants, haves)?;
+    git::read_fetch_response(body, capabilities, options)
+}
+
+/// writes every object of `packet` loose into `git_dir`
+#[cfg(not(feature = "parallel"))]
+fn persist_objects(git_dir: &Path, packet: &git::Packet) -> anyhow::Result<()> {
+    for obj in packet.objects.values() {
+        obj.persist_in(git_dir)?;
+    }
+    Ok(())
+}
+
+/// writes every object of `packet` loose into `git_dir`, spreading the compression
+/// work over a thread pool
+#[cfg(feature = "parallel")]
+fn persist_objects(git_dir: &Path, packet: &git::Packet) -> anyhow::Result<()> {
+    use rayon::prelude::*;
+    packet
+        .objects
+        .par_iter()
+        .try_for_each(|(_, obj)| obj.persist_in(git_dir).map(|_| ()))
+}
+
+/// parses the pack as it is read from `pack`, which must end with it. bases of thin
+/// pack deltas are looked up among the objects already in `git_dir`.
+fn receive_pack(
+    git_dir: &Path,
+    pack: impl Read,
+    verbosity: git::Verbosity,
+) -> anyhow::Result<git::Packet> {
+    let mut pack = std::io::BufReader::new(pack);
+    let packet = git::Packet::from_reader_with_bases(&mut pack, verbosity, |base| {
+        git::Object::load_in(git_dir, &hex::encode(base)).ok()
+    })?;
+    anyhow::ensure!(
+        pack.fill_buf()?.is_empty(),
+        "unexpected data after the pack checksum"
+    );
+    Ok(packet)
+}
+
+/// saves the pack read from `pack` under `objects/pack` along with its index, instead
+/// of exploding it into loose objects, and returns its checksum. the pack is written
+/// to a temporary file as it comes in, and named after its checksum once it checks out.
+fn keep_pack(
+    git_dir: &Path,
+    mut pack: impl Read,
+    verbosity: git::Verbosity,
+) -> anyhow::Result<[u8; 20]> {
+    let dir = git_dir.join("objects").join("pack");
+    fs::create_dir_all(&dir).context("failed to create the pack directory")?;
+    let tmp = dir.join(format!("tmp_pack_{}", std::process::id()));
+    let result = (|| {
+        let mut file = fs::File::create(&tmp).context(format!("failed to create {tmp:?}"))?;
+        std::io::copy(&mut pack, &mut file).context("failed to receive the pack")?;
+        drop(file);
+        let file = fs::File::open(&tmp).context(format!("failed to open {tmp:?}"))?;
+        let packet = receive_pack(git_dir, file, verbosity)?;
+        let path = dir.join(format!("pack-{}.pack", hex::encode(packet.checksum)));
+        fs::rename(&tmp, &path).context(format!("failed to write the pack {path:?}"))?;
+        git::PackIndex::from_packet(&packet).persist(&path.with_extension("idx"))?;
+        Ok(packet.checksum)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// checks the tree of the commit `head_hash` out into `current_dir`
+fn build_from_head(
+    head_hash: &str,
+    git_dir: &Path,
+    current_dir: &Path,
+    progress: &mut git::Progress,
+) -> anyhow::Result<()> {
+    let commit = git::Commit::try_from(git::Object::load_in(git_dir, head_hash)?)?;
+    build_tree(
+        &commit.tree,
+        git_dir,
+        current_dir,
+        &mut HashSet::new(),
+        progress,
+    )
+}
+
+/// materializes the tree `hash` into `current_dir`. `built` records the objects already
+/// materialized along with where, so none is written twice.
+fn build_tree(
+    hash: &str,
+    git_dir: &Path,
+    current_dir: &Path,
+    built: &mut HashSet<([u8; 20], PathBuf)>,
+    progress: &mut git::Progress,
+) -> anyhow::Result<()> {
+    let raw_hash = <[u8; 20]>::try_from(hex::decode(hash)?)
+        .map_err(|_| anyhow!("{hash} is not a valid object name"))?;
+    if !built.insert((raw_hash, current_dir.to_path_buf())) {
+        return Ok(());
+    }
+    progress.log(|| format!("fetching tree: {hash}"));
+
+    let obj = git::Object::load_in(git_dir, hash)?;
+    let tree = git::Tree::try_from(obj)?;
+    for node in tree.nodes {
+        match node.kind {
+            git::NodeKind::Dir { .. } => {
+                let dir_path = current_dir.join(&node.name);
+                // a symlink checked out under the same name, or already there, would take
+                // the files of the tree wherever it points
+                let metadata = dir_path.symlink_metadata();
+                anyhow::ensure!(
+                    !metadata.is_ok_and(|m| m.file_type().is_symlink()),
+                    "refusing to check out tree {} through a symlink",
+                    node.name
+                );
+                std::fs::create_dir_all(&dir_path).context(format!(
+                    "failed to create a directory for tree {}",
+                    node.name
+                ))?;
+                build_tree(&hex::encode(node.hash), git_dir, &dir_path, built, progress)?;
+            }
+            git::NodeKind::File { .. } | git::NodeKind::SymLink { .. } => {
+                if built.insert((node.hash, current_dir.join(&node.name))) {
+                    build_file(&node, git_dir, current_dir, progress)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn build_file(
+    node: &git::Node,
+    git_dir: &Path,
+    current_dir: &Path,
+    progress: &mut git::Progress,
+) -> anyhow::Result<()> {
+    let file_path = current_dir.join(&node.name);
+    // symlink_metadata does not follow links, so dangling symlinks count as present too
+    if file_path.symlink_metadata().is_ok() {
+        return Ok(());
+    }
+    progress.tick(|| format!("fetching file: {} [{}]", node.name, hex::encode(node.hash)));
+
+    let obj = git::Object::load_in(git_dir, &hex::encode(node.hash))?;
+
+    if let git::NodeKind::SymLink { .. } = node.kind {
+        // the blob of a symlink holds the raw bytes of the path it points to
+        git::platform::symlink(&obj.body, &file_path)
+            .context(format!("failed to create symlink {}", node.name))?;
+        return Ok(());
+    }
+
+    // create file with correct permissions
+    std::fs::File::create(&file_path)?;
+    git::platform::set_mode(&file_path, node.kind.mode())?;
 
     std::fs::write(&file_path, &obj.body)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// the tip of the history held by the `ofs-delta` pack fixture, where `nums.txt`
+    /// went from `seq 1 300` through two single-line edits. the older versions are
+    /// offset deltas against the newer ones.
+    const FIXTURE_HEAD: &str = "99ad0c5a6ec1ae97f8d334278a4bd3eed15df1a2";
+    const FIXTURE_FIRST_BLOB: &str = "e9f1816de795d8e46914856d53c0f1de4291ce89";
+
+    /// lays out a repository in `dir` whose objects are all in the fixture pack
+    fn packed_repo(dir: &Path) -> PathBuf {
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(&git_dir).unwrap();
+        init_git_dir(&git_dir, false, Some("master")).unwrap();
+        let pack_dir = git_dir.join("objects").join("pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        for ext in ["pack", "idx"] {
+            let name = format!("pack-693729a18f137ec55e046f3a0723ee96b69e635b.{ext}");
+            fs::copy(
+                fixtures.join(format!("ofs-delta.{ext}")),
+                pack_dir.join(name),
+            )
+            .unwrap();
+        }
+        git::refs::write_ref(&git_dir, "refs/heads/master", FIXTURE_HEAD).unwrap();
+        git_dir
+    }
+
+    /// runs `f` with `GIT_DIR` pointing at `git_dir`, for the commands that work on the
+    /// repository of the environment. the tests doing so take turns.
+    fn with_git_dir<T>(git_dir: &Path, f: impl FnOnce() -> T) -> T {
+        static ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let _guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_var("GIT_DIR", git_dir);
+        let result = f();
+        std::env::remove_var("GIT_DIR");
+        result
+    }
+
+    fn quiet_clone() -> CloneOptions {
+        CloneOptions {
+            depth: None,
+            branch: None,
+            bare: false,
+            keep_pack: false,
+            verbosity: git::Verbosity::Quiet,
+        }
+    }
+
+    #[test]
+    fn reads_offset_deltas_from_stored_packs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = packed_repo(tmp.path());
+        let blob = git::Object::load_in(&git_dir, FIXTURE_FIRST_BLOB).unwrap();
+        let expected: String = (1..=300).map(|n| format!("{n}\n")).collect();
+        assert_eq!(blob.body, expected.as_bytes());
+        let reader = git::Object::open_in(&git_dir, FIXTURE_FIRST_BLOB).unwrap();
+        assert_eq!(reader.kind, git::ObjectKind::Blob);
+    }
+
+    #[test]
+    fn clones_a_local_repository_of_packed_objects() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        packed_repo(&source);
+        let dst = tmp.path().join("clone");
+        git_clone(source.to_str().unwrap(), &dst, &quiet_clone()).unwrap();
+
+        let git_dir = dst.join(".git");
+        assert_eq!(git::refs::read_ref(&git_dir, "HEAD").unwrap(), FIXTURE_HEAD);
+        assert_eq!(
+            git::refs::read_ref(&git_dir, "refs/remotes/origin/master").unwrap(),
+            FIXTURE_HEAD
+        );
+        let expected: String = (1..=300)
+            .map(|n| match n {
+                10 => "ten\n".to_string(),
+                150 => "one-fifty\n".to_string(),
+                n => format!("{n}\n"),
+            })
+            .collect();
+        assert_eq!(fs::read_to_string(dst.join("nums.txt")).unwrap(), expected);
+        // the copied pack still serves the deltified history
+        for commit in git::RevWalk::new(&git_dir, [FIXTURE_HEAD.to_string()]).unwrap() {
+            let (_, commit) = commit.unwrap();
+            let tree = git::Tree::try_from(git::Object::load_in(&git_dir, &commit.tree).unwrap());
+            assert_eq!(tree.unwrap().nodes.len(), 1);
+        }
+        git::Object::load_in(&git_dir, FIXTURE_FIRST_BLOB).unwrap();
+    }
+
+    #[test]
+    fn treats_drive_letters_as_local_paths() {
+        assert_eq!(local_source(r"C:\repo"), Some(PathBuf::from(r"C:\repo")));
+        assert_eq!(
+            local_source("d:/src/repo"),
+            Some(PathBuf::from("d:/src/repo"))
+        );
+        assert_eq!(local_source("host:repo.git"), None);
+        assert_eq!(local_source("git@host:/repo.git"), None);
+        assert_eq!(
+            local_source("file:///srv/repo"),
+            Some(PathBuf::from("/srv/repo"))
+        );
+    }
+
+    #[test]
+    fn clones_a_local_repository_that_borrows_its_objects() {
+        let tmp = tempfile::tempdir().unwrap();
+        packed_repo(&tmp.path().join("lender"));
+        let source = tmp.path().join("borrower");
+        let source_git_dir = source.join(".git");
+        fs::create_dir_all(&source_git_dir).unwrap();
+        init_git_dir(&source_git_dir, false, Some("master")).unwrap();
+        let info = source_git_dir.join("objects").join("info");
+        fs::create_dir_all(&info).unwrap();
+        fs::write(
+            info.join("alternates"),
+            "# borrowed\n../../../lender/.git/objects\n",
+        )
+        .unwrap();
+        git::refs::write_ref(&source_git_dir, "refs/heads/master", FIXTURE_HEAD).unwrap();
+
+        let dst = tmp.path().join("clone");
+        git_clone(source.to_str().unwrap(), &dst, &quiet_clone()).unwrap();
+        let alternates = fs::read_to_string(dst.join(".git/objects/info/alternates")).unwrap();
+        let lender = fs::canonicalize(tmp.path().join("lender/.git/objects")).unwrap();
+        assert_eq!(alternates, format!("{}\n", lender.display()));
+        assert!(dst.join("nums.txt").is_file());
+        git::Object::load_in(&dst.join(".git"), FIXTURE_FIRST_BLOB).unwrap();
+    }
+
+    /// the history of the `two-commits` pack fixture, which `third-commit` extends as a
+    /// thin pack whose one delta is against an object of `two-commits`
+    const FIRST: &str = "04d46590411d266e2c6312fb8b5eba35d1973522";
+    const SECOND: &str = "e14c4d338a2f1d601d6fcfd7752d9af3c726c943";
+    const THIRD: &str = "5a69049fe492ac5717e852df5ce453875dfb6e57";
+
+    /// serves a canned ref advertisement and a canned response to every fetch, and
+    /// records the wants and haves it was asked for
+    struct MockTransport {
+        advertisement: Vec<u8>,
+        response: Vec<u8>,
+        requests: RefCell<Vec<(Vec<String>, Vec<String>)>>,
+    }
+
+    impl MockTransport {
+        /// advertises `refs` with the capabilities of a v0 server, and answers with a
+        /// `NAK` followed by `pack` on side-band 1
+        fn new(refs: &[(&str, &str)], pack: &[u8]) -> Self {
+            let capabilities = "multi_ack_detailed side-band-64k ofs-delta \
+                symref=HEAD:refs/heads/master agent=git/2.43.0";
+            let mut advertisement = Vec::new();
+            for (i, (name, hash)) in refs.iter().enumerate() {
+                let line = match i {
+                    0 => format!("{hash} {name}\0{capabilities}\n"),
+                    _ => format!("{hash} {name}\n"),
+                };
+                git::PacketLine::Data(line.into_bytes()).encode(&mut advertisement);
+            }
+            git::PacketLine::Flush.encode(&mut advertisement);
+
+            let mut response = Vec::new();
+            git::PacketLine::Data(b"NAK\n".to_vec()).encode(&mut response);
+            for chunk in pack.chunks(1000) {
+                let data = [&[1u8][..], chunk].concat();
+                git::PacketLine::Data(data).encode(&mut response);
+            }
+            git::PacketLine::Flush.encode(&mut response);
+            Self {
+                advertisement,
+                response,
+                requests: RefCell::default(),
+            }
+        }
+    }
+
+    impl git::Transport for MockTransport {
+        fn url(&self) -> &str {
+            "https://example.com/repo.git"
+        }
+
+        fn advertise_refs(&self) -> anyhow::Result<(Vec<git::refs::Ref>, git::Capabilities)> {
+            let lines = git::packet_lines(&self.advertisement)
+                .map(|line| line.map(git::PacketLineRef::into_owned))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            git::refs::parse_advertisement(lines.into_iter().take_while(|p| !p.is_flush()))
+        }
+
+        fn fetch(&self, wants: &[String], haves: &[String]) -> anyhow::Result<Box<dyn Read + '_>> {
+            self.requests
+                .borrow_mut()
+                .push((wants.to_vec(), haves.to_vec()));
+            Ok(Box::new(std::io::Cursor::new(self.response.clone())))
+        }
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        fs::read(path).unwrap()
+    }
+
+    /// clones the `two-commits` fixture into `dst` through a mock transport
+    fn mock_clone(dst: &Path) -> MockTransport {
+        let transport = MockTransport::new(
+            &[("HEAD", SECOND), ("refs/heads/master", SECOND)],
+            &fixture("two-commits.pack"),
+        );
+        let options = quiet_clone();
+        clone_into(dst, &options, |git_dir| {
+            clone_remote(&transport, git_dir, &options)
+        })
+        .unwrap();
+        transport
+    }
+
+    #[test]
+    fn clones_through_a_transport() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("clone");
+        let transport = mock_clone(&dst);
+        let requests = transport.requests.into_inner();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].0.iter().all(|hex| hex == SECOND));
+        assert!(requests[0].1.is_empty());
+
+        let git_dir = dst.join(".git");
+        assert_eq!(
+            fs::read_to_string(dst.join("README")).unwrap(),
+            "hello\nworld\n"
+        );
+        let nums: String = (1..=200).map(|n| format!("{n}\n")).collect();
+        assert_eq!(fs::read_to_string(dst.join("src/nums.txt")).unwrap(), nums);
+        assert_eq!(git::refs::read_ref(&git_dir, "HEAD").unwrap(), SECOND);
+        assert_eq!(
+            git::refs::read_symbolic_ref(&git_dir, "HEAD")
+                .unwrap()
+                .as_deref(),
+            Some("refs/heads/master")
+        );
+        assert_eq!(
+            git::refs::read_ref(&git_dir, "refs/remotes/origin/master").unwrap(),
+            SECOND
+        );
+        let config = git::Config::load(&git_dir).unwrap();
+        assert_eq!(
+            config.get("remote.origin.url"),
+            Some("https://example.com/repo.git")
+        );
+        let first = git::Commit::try_from(git::Object::load_in(&git_dir, FIRST).unwrap());
+        assert!(first.unwrap().parents.is_empty());
+    }
+
+    #[test]
+    fn fetches_a_thin_pack_through_a_transport() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("clone");
+        mock_clone(&dst);
+        let git_dir = dst.join(".git");
+
+        let transport = MockTransport::new(
+            &[("HEAD", THIRD), ("refs/heads/master", THIRD)],
+            &fixture("third-commit.pack"),
+        );
+        fetch_from(&git_dir, &transport, &Default::default()).unwrap();
+        let requests = transport.requests.into_inner();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, [THIRD]);
+        assert!(requests[0].1.iter().any(|hex| hex == SECOND));
+
+        assert_eq!(
+            git::refs::read_ref(&git_dir, "refs/remotes/origin/master").unwrap(),
+            THIRD
+        );
+        // the delta of the changed file was resolved against the blob already there
+        let third = git::Commit::try_from(git::Object::load_in(&git_dir, THIRD).unwrap()).unwrap();
+        assert_eq!(third.parents, [SECOND]);
+        let tree = git::Tree::try_from(git::Object::load_in(&git_dir, &third.tree).unwrap());
+        let src = tree
+            .unwrap()
+            .nodes
+            .into_iter()
+            .find(|n| n.name == "src")
+            .unwrap();
+        let src =
+            git::Tree::try_from(git::Object::load_in(&git_dir, &hex::encode(src.hash)).unwrap());
+        let nums = &src.unwrap().nodes[0];
+        let blob = git::Object::load_in(&git_dir, &hex::encode(nums.hash)).unwrap();
+        assert!(String::from_utf8(blob.body)
+            .unwrap()
+            .contains("\none hundred\n"));
+    }
+
+    #[test]
+    fn fetch_leaves_moved_tags_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("clone");
+        mock_clone(&dst);
+        let git_dir = dst.join(".git");
+        git::refs::write_ref(&git_dir, "refs/tags/v1", FIRST).unwrap();
+
+        let transport = MockTransport::new(
+            &[
+                ("HEAD", THIRD),
+                ("refs/heads/master", THIRD),
+                ("refs/tags/v1", SECOND),
+            ],
+            &fixture("third-commit.pack"),
+        );
+        let err = fetch_from(&git_dir, &transport, &Default::default()).unwrap_err();
+        assert!(err.to_string().contains("could not be updated"), "{err}");
+        assert_eq!(
+            git::refs::read_ref(&git_dir, "refs/tags/v1").unwrap(),
+            FIRST
+        );
+        assert_eq!(
+            git::refs::read_ref(&git_dir, "refs/remotes/origin/master").unwrap(),
+            THIRD
+        );
+    }
+
+    #[test]
+    fn walks_stop_at_the_shallow_boundary() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("clone");
+        mock_clone(&dst);
+        let git_dir = dst.join(".git");
+        let transport = MockTransport::new(
+            &[("HEAD", THIRD), ("refs/heads/master", THIRD)],
+            &fixture("third-commit.pack"),
+        );
+        fetch_from(&git_dir, &transport, &Default::default()).unwrap();
+        // as if the clone had been made with --depth 2
+        fs::write(git_dir.join("shallow"), format!("{SECOND}\n")).unwrap();
+        let first = git::ObjectId::try_from(FIRST).unwrap();
+        fs::remove_file(git_dir.join("objects").join(first.loose_path())).unwrap();
+
+        let walked: Vec<String> = git::RevWalk::new(&git_dir, [THIRD.to_string()])
+            .unwrap()
+            .map(|commit| commit.unwrap().0)
+            .collect();
+        assert_eq!(walked, [THIRD, SECOND]);
+        let base = merge_base(&git_dir, THIRD, SECOND).unwrap();
+        assert_eq!(base.as_deref(), Some(SECOND));
+    }
+
+    #[test]
+    fn checks_out_only_the_tree_of_head() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("clone");
+        mock_clone(&dst);
+        let git_dir = dst.join(".git");
+        // goes back to the tree of the first commit, which has no `src`
+        let first = git::Commit::try_from(git::Object::load_in(&git_dir, FIRST).unwrap());
+        let body = format!(
+            "tree {}\nparent {SECOND}\nauthor A U Thor <author@example.com> 1112912053 +0000\n\
+             committer A U Thor <author@example.com> 1112912053 +0000\n\nrevert\n",
+            first.unwrap().tree
+        );
+        let revert = git::Object::new(git::ObjectKind::Commit, body.into_bytes());
+        let revert = hex::encode(revert.persist_in(&git_dir).unwrap());
+
+        let worktree = tmp.path().join("worktree");
+        fs::create_dir(&worktree).unwrap();
+        let mut progress = git::Progress::new("Updating files", None, git::Verbosity::Quiet);
+        build_from_head(&revert, &git_dir, &worktree, &mut progress).unwrap();
+        assert_eq!(
+            fs::read_to_string(worktree.join("README")).unwrap(),
+            "hello\n"
+        );
+        assert!(!worktree.join("src").exists());
+    }
+
+    #[test]
+    fn writes_the_refs_of_a_clone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        let source_git_dir = packed_repo(&source);
+        git::refs::write_ref(&source_git_dir, "refs/heads/topic", FIXTURE_HEAD).unwrap();
+        git::refs::write_ref(&source_git_dir, "refs/tags/v1", FIXTURE_HEAD).unwrap();
+        let dst = tmp.path().join("clone");
+        git_clone(source.to_str().unwrap(), &dst, &quiet_clone()).unwrap();
+
+        let git_dir = dst.join(".git");
+        let read = |name: &str| fs::read_to_string(git_dir.join(name)).unwrap();
+        assert_eq!(read("HEAD"), "ref: refs/heads/master\n");
+        assert_eq!(read("refs/heads/master"), format!("{FIXTURE_HEAD}\n"));
+        assert_eq!(
+            read("refs/remotes/origin/master"),
+            format!("{FIXTURE_HEAD}\n")
+        );
+        assert_eq!(
+            read("refs/remotes/origin/topic"),
+            format!("{FIXTURE_HEAD}\n")
+        );
+        assert_eq!(
+            read("refs/remotes/origin/HEAD"),
+            "ref: refs/remotes/origin/master\n"
+        );
+        assert_eq!(read("refs/tags/v1"), format!("{FIXTURE_HEAD}\n"));
+        // only the checked out branch is created locally
+        assert!(!git_dir.join("refs/heads/topic").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checks_out_symlinks_as_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        init_git_dir(&git_dir, false, None).unwrap();
+        let blob = |body: &[u8]| {
+            let blob = git::Object::new(git::ObjectKind::Blob, body.to_vec());
+            blob.persist_in(&git_dir).unwrap()
+        };
+        let mut tree = git::TreeBuilder::new();
+        tree.add(0o100644, "target.txt", blob(b"hi\n"));
+        tree.add(0o120000, "link", blob(b"target.txt"));
+        tree.add(0o120000, "dangling", blob(b"nowhere/else"));
+        let tree = hex::encode(tree.build().persist_in(&git_dir).unwrap());
+
+        let worktree = tmp.path().join("worktree");
+        fs::create_dir(&worktree).unwrap();
+        let mut progress = git::Progress::new("Updating files", None, git::Verbosity::Quiet);
+        build_tree(
+            &tree,
+            &git_dir,
+            &worktree,
+            &mut HashSet::new(),
+            &mut progress,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_link(worktree.join("link")).unwrap(),
+            Path::new("target.txt")
+        );
+        assert_eq!(fs::read_to_string(worktree.join("link")).unwrap(), "hi\n");
+        assert_eq!(
+            fs::read_link(worktree.join("dangling")).unwrap(),
+            Path::new("nowhere/else")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_symlinks_into_trees_as_their_target() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        init_git_dir(&git_dir, false, None).unwrap();
+        fs::write(tmp.path().join("a.txt"), "hi\n").unwrap();
+        std::os::unix::fs::symlink("a.txt", tmp.path().join("link")).unwrap();
+        std::os::unix::fs::symlink("missing/target", tmp.path().join("dangling")).unwrap();
+
+        let tree = with_git_dir(&git_dir, || write_tree_from_dir(tmp.path(), Path::new("")));
+        // as `git write-tree` names the same tree
+        assert_eq!(
+            hex::encode(tree.unwrap()),
+            "5eab4005b37a93e44afd7623c0171f8c6b164313"
+        );
+        let link = git::Object::load_in(&git_dir, "8d14cbf983b3fad683171c9418998d9f68340823");
+        assert_eq!(link.unwrap().body, b"a.txt");
+    }
+
+    #[test]
+    fn sorts_directories_as_if_they_ended_in_a_slash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        init_git_dir(&git_dir, false, None).unwrap();
+        // a plain sort would put `foo` first, git puts it between `foo.txt` and `foo0`
+        fs::create_dir(tmp.path().join("foo")).unwrap();
+        fs::write(tmp.path().join("foo/inner.txt"), "inner\n").unwrap();
+        fs::write(tmp.path().join("foo.txt"), "a\n").unwrap();
+        fs::write(tmp.path().join("foo-bar"), "b\n").unwrap();
+        fs::write(tmp.path().join("foo0"), "c\n").unwrap();
+
+        let tree = with_git_dir(&git_dir, || write_tree_from_dir(tmp.path(), Path::new("")));
+        let tree = hex::encode(tree.unwrap());
+        assert_eq!(tree, "8739353a0f415c5e821c480c052b54c9b3d4f033");
+        let tree = git::Tree::try_from(git::Object::load_in(&git_dir, &tree).unwrap());
+        let names: Vec<_> = tree.unwrap().nodes.into_iter().map(|n| n.name).collect();
+        assert_eq!(names, ["foo-bar", "foo.txt", "foo", "foo0"]);
+    }
+
+    #[test]
+    fn unpacks_every_object_of_a_pack() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        init_git_dir(&git_dir, false, None).unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        unpack_objects(&git_dir, &fixtures.join("two-commits.pack")).unwrap();
+        // the thin pack's delta resolves against the objects unpacked before it
+        unpack_objects(&git_dir, &fixtures.join("third-commit.pack")).unwrap();
+
+        for name in ["two-commits.pack", "third-commit.pack"] {
+            let raw = fixture(name);
+            let packet = git::Packet::from_bytes_with_bases(&raw, git::Verbosity::Quiet, |base| {
+                git::Object::load_in(&git_dir, &hex::encode(base)).ok()
+            })
+            .unwrap();
+            for (hash, object) in &packet.objects {
+                let loaded = git::Object::load_in(&git_dir, &hex::encode(hash)).unwrap();
+                assert_eq!(loaded.kind(), object.kind());
+                assert_eq!(loaded.body, object.body);
+            }
+        }
+        let walked = git::RevWalk::new(&git_dir, [THIRD.to_string()]).unwrap();
+        assert_eq!(walked.count(), 3);
+    }
+
+    #[test]
+    fn writes_a_default_config_on_init() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        assert!(!init_git_dir(&git_dir, false, Some("main")).unwrap());
+        let filemode = cfg!(unix).to_string();
+        assert_eq!(
+            fs::read_to_string(git_dir.join("config")).unwrap(),
+            format!(
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = {filemode}\n\
+                 \tbare = false\n\tlogallrefupdates = true\n"
+            )
+        );
+        assert_eq!(
+            git::refs::read_symbolic_ref(&git_dir, "HEAD")
+                .unwrap()
+                .as_deref(),
+            Some("refs/heads/main")
+        );
+
+        // a re-init keeps the settings that are already there
+        let mut config = git::Config::load(&git_dir).unwrap();
+        config.set("user.name", "someone").unwrap();
+        config.persist(&git_dir).unwrap();
+        assert!(init_git_dir(&git_dir, false, Some("other")).unwrap());
+        let config = git::Config::load(&git_dir).unwrap();
+        assert_eq!(config.get("user.name"), Some("someone"));
+
+        let bare = tmp.path().join("bare.git");
+        init_git_dir(&bare, true, Some("main")).unwrap();
+        let config = git::Config::load(&bare).unwrap();
+        assert_eq!(config.get("core.bare"), Some("true"));
+        assert_eq!(config.get("core.logallrefupdates"), None);
+    }
+
+    #[test]
+    fn normalizes_messages_like_git_commit() {
+        let headers = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                       author A <a@example.com> 1112911993 +0200\n\
+                       committer A <a@example.com> 1112911993 +0200\n";
+        for message in [
+            "\r\n\r\nsubject line\r\n\r\nbody first\r\nbody second\r\n\r\n\r\n",
+            "subject line\n\nbody first\nbody second",
+            "subject line\n\nbody first\nbody second\n",
+        ] {
+            let content = format!("{headers}\n{}", normalize_message(message));
+            let commit = git::Object::new(git::ObjectKind::Commit, content.into_bytes());
+            // as `git commit -F` names the first of them
+            assert_eq!(
+                commit.id().to_string(),
+                "87be22838fbd8fd1ac15122b3e68bf944acd69a9",
+                "{message:?}"
+            );
+            let commit = git::Commit::try_from(commit).unwrap();
+            assert_eq!(commit.message, "subject line\n\nbody first\nbody second\n");
+        }
+    }
+
+    #[test]
+    fn refuses_to_check_out_outside_the_worktree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        init_git_dir(&git_dir, false, None).unwrap();
+        let blob = git::Object::new(git::ObjectKind::Blob, b"pwned\n".to_vec());
+        let blob = blob.persist_in(&git_dir).unwrap();
+        let worktree = tmp.path().join("worktree");
+        fs::create_dir(&worktree).unwrap();
+
+        for name in ["../escaped", ".git", ".Git"] {
+            let mut tree = git::TreeBuilder::new();
+            tree.add(0o100644, name, blob);
+            let tree = tree.build().persist_in(&git_dir).unwrap();
+            // nested a level down too, where `..` only leaves the subdirectory
+            let mut outer = git::TreeBuilder::new();
+            outer.add(0o40000, "sub", tree);
+            let outer = outer.build().persist_in(&git_dir).unwrap();
+            for hash in [tree, outer] {
+                assert!(checkout(&hex::encode(hash), &git_dir, &worktree).is_err());
+            }
+        }
+        assert!(!tmp.path().join("escaped").exists());
+        assert!(!worktree.join("sub/escaped").exists());
+        assert!(!git_dir.join("escaped").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_to_check_out_through_symlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        init_git_dir(&git_dir, false, None).unwrap();
+        let outside = tmp.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let blob = |body: &[u8]| {
+            let blob = git::Object::new(git::ObjectKind::Blob, body.to_vec());
+            blob.persist_in(&git_dir).unwrap()
+        };
+        let mut inner = git::TreeBuilder::new();
+        inner.add(0o100644, "file", blob(b"pwned\n"));
+        let inner = inner.build().persist_in(&git_dir).unwrap();
+        let mut tree = git::TreeBuilder::new();
+        tree.add(0o120000, "a", blob(outside.to_str().unwrap().as_bytes()));
+        tree.add(0o40000, "a", inner);
+        let tree = hex::encode(tree.build().persist_in(&git_dir).unwrap());
+
+        let worktree = tmp.path().join("worktree");
+        fs::create_dir(&worktree).unwrap();
+        let err = checkout(&tree, &git_dir, &worktree).unwrap_err();
+        assert!(err.to_string().contains("through a symlink"), "{err}");
+        assert!(!outside.join("file").exists());
+
+        // a symlink already in the worktree is not followed either
+        let mut tree = git::TreeBuilder::new();
+        tree.add(0o40000, "b", inner);
+        let tree = hex::encode(tree.build().persist_in(&git_dir).unwrap());
+        std::os::unix::fs::symlink(&outside, worktree.join("b")).unwrap();
+        assert!(checkout(&tree, &git_dir, &worktree).is_err());
+        assert!(!outside.join("file").exists());
+    }
+
+    #[test]
+    fn refuses_ssh_hosts_that_look_like_options() {
+        for url in [
+            "ssh://-oProxyCommand=touch${IFS}pwned/repo.git",
+            "ssh://host:-oProxyCommand=sh/repo.git",
+        ] {
+            assert!(connect(url, &git::FetchOptions::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn clones_every_ref_when_checking_out_a_branch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("clone");
+        let transport = MockTransport::new(
+            &[
+                ("HEAD", SECOND),
+                ("refs/heads/master", SECOND),
+                ("refs/heads/topic", FIRST),
+                ("refs/tags/v1", FIRST),
+            ],
+            &fixture("two-commits.pack"),
+        );
+        let options = CloneOptions {
+            branch: Some("topic".into()),
+            ..quiet_clone()
+        };
+        clone_into(&dst, &options, |git_dir| {
+            clone_remote(&transport, git_dir, &options)
+        })
+        .unwrap();
+
+        let wants = &transport.requests.borrow()[0].0;
+        assert!(wants.contains(&FIRST.to_string()) && wants.contains(&SECOND.to_string()));
+        let git_dir = dst.join(".git");
+        assert_eq!(git::refs::read_ref(&git_dir, "HEAD").unwrap(), FIRST);
+        for name in ["refs/remotes/origin/master", "refs/tags/v1"] {
+            let hash = git::refs::read_ref(&git_dir, name).unwrap();
+            assert!(git::Object::load_in(&git_dir, &hash).is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn fetches_into_a_shallow_repository() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("clone");
+        mock_clone(&dst);
+        let git_dir = dst.join(".git");
+        fs::write(git_dir.join("shallow"), format!("{FIRST}\n{SECOND}\n")).unwrap();
+
+        let mut transport = MockTransport::new(
+            &[("HEAD", THIRD), ("refs/heads/master", THIRD)],
+            &fixture("third-commit.pack"),
+        );
+        // the remote fills in the history behind one of the shallow commits
+        let mut response = Vec::new();
+        git::PacketLine::Data(format!("unshallow {SECOND}\n").into_bytes()).encode(&mut response);
+        git::PacketLine::Flush.encode(&mut response);
+        response.extend(transport.response);
+        transport.response = response;
+        let options = git::FetchOptions {
+            shallow: vec![FIRST.into(), SECOND.into()],
+            ..Default::default()
+        };
+        fetch_from(&git_dir, &transport, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(git_dir.join("shallow")).unwrap(),
+            format!("{FIRST}\n")
+        );
+        assert_eq!(
+            git::refs::read_ref(&git_dir, "refs/remotes/origin/master").unwrap(),
+            THIRD
+        );
+    }
+}
@@ -1,12 +1,23 @@
-use anyhow::{anyhow, Context, Ok};
+use anyhow::{anyhow, Context};
 use sha1::Digest;
+
+use crate::attributes::normalize_eol;
+use crate::packet::{apply_delta, read_packed_object, PackedObject};
+use crate::{GitError, ObjectId, PackIndex, Signature, Text};
 use std::{
+    collections::{HashMap, VecDeque},
     fmt::Display,
-    io::{BufRead, BufReader, Read},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     str,
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
 };
 
+/// alternates listed by alternates are followed at most this many levels deep, like
+/// git does
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
 #[derive(Debug, Clone)]
 pub struct Object {
     pub(crate) kind: ObjectKind,
@@ -14,6 +25,14 @@ pub struct Object {
 }
 
 impl Object {
+    pub fn kind(&self) -> &ObjectKind {
+        &self.kind
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.hash().into()
+    }
+
     pub fn hash(&self) -> [u8; 20] {
         let mut hasher = sha1::Sha1::new();
         hasher.update(format!("{} {}\0", self.kind, self.body.len()));
@@ -22,77 +41,455 @@ impl Object {
         sha1sum.into()
     }
     pub fn persist(&self) -> anyhow::Result<[u8; 20]> {
-        self.persist_in(&PathBuf::from("."))
+        self.persist_in(&crate::repo::git_dir())
     }
-    pub fn persist_in(&self, dst: &Path) -> anyhow::Result<[u8; 20]> {
+    /// writes the object into the object repository of the git directory `git_dir`
+    pub fn persist_in(&self, git_dir: &Path) -> anyhow::Result<[u8; 20]> {
+        self.persist_with(git_dir, flate2::Compression::default())
+    }
+    /// like `persist_in`, compressing the object with the given zlib `level`
+    pub fn persist_with(
+        &self,
+        git_dir: &Path,
+        level: flate2::Compression,
+    ) -> anyhow::Result<[u8; 20]> {
         let hash = self.hash();
-        let path = dst.join(Object::path(&hash));
+        let path = git_dir
+            .join("objects")
+            .join(ObjectId::from(hash).loose_path());
         Object::ensure_dir(
             path.parent()
                 .ok_or(anyhow!("failed to ensure parent directory for object"))?,
         )?;
-        let mut raw = format!("{} {}\0", self.kind, self.body.len())
-            .as_bytes()
-            .to_vec();
-        raw.extend(&self.body);
-
-        let mut zlib_encoder =
-            flate2::read::ZlibEncoder::new(&raw[..], flate2::Compression::none());
-        let mut buf = Vec::new();
-        zlib_encoder.read_to_end(&mut buf)?;
-        std::fs::write(path, buf)?;
+        let file = std::fs::File::create(&path)
+            .context(format!("failed to create the object file {path:?}"))?;
+        let mut zlib_encoder = flate2::write::ZlibEncoder::new(file, level);
+        write!(zlib_encoder, "{} {}\0", self.kind, self.body.len())?;
+        zlib_encoder.write_all(&self.body)?;
+        zlib_encoder.finish()?;
         Ok(hash)
     }
 
     /// loads object from the object repository using the hex represntation of its hash
-    pub fn load(hex: &str) -> anyhow::Result<Self> {
-        let object = std::fs::File::open(Object::path_from_hex(hex))
-            .context(format!("failed to find the object file for {hex}"))?;
-        Object::new_object_from(object)
+    pub fn load(hex: &str) -> Result<Self, GitError> {
+        Self::load_in(&crate::repo::git_dir(), hex)
     }
+    /// loads object from the object repository of the git directory `git_dir`,
+    /// making sure its content hashes to the name it was looked up under
+    pub fn load_in(git_dir: &Path, hex: &str) -> Result<Self, GitError> {
+        let id = match hex.len() {
+            40 => hex.parse()?,
+            _ => Object::expand_prefix_in(git_dir, hex)?.parse()?,
+        };
+        let mut r = Object::open_id_in(git_dir, &id)?;
+        let size = r.size;
+        let body = read_body(&mut r, size)?;
 
-    /// creates new object from the byte stream
-    pub fn new_object_from<R: Read>(raw: R) -> anyhow::Result<Self> {
-        let zlib_decoder = flate2::read::ZlibDecoder::new(raw);
-        let mut r = BufReader::new(zlib_decoder);
+        let object = Self { kind: r.kind, body };
+        let actual = object.id();
+        if actual != id {
+            return Err(GitError::corrupt(format!(
+                "hash mismatch for {id}, content hashes to {actual}"
+            )));
+        }
+        Ok(object)
+    }
 
-        //zlib_decoder.read_to_end(&mut buf)?;
-        let mut buf = Vec::new();
+    /// finds the full hex hash of the single object in `git_dir`, or its alternates, whose
+    /// hash starts with `prefix`
+    pub fn expand_prefix_in(git_dir: &Path, prefix: &str) -> Result<String, GitError> {
+        if prefix.len() < 4 || prefix.len() > 40 || !prefix.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(GitError::InvalidObjectName(prefix.into()));
+        }
+        let prefix = prefix.to_ascii_lowercase();
+        let (dir, rest) = prefix.split_at(2);
+        let mut matches = Vec::new();
+        let mut objects_dirs = vec![git_dir.join("objects")];
+        objects_dirs.extend(Object::alternates(git_dir)?);
+        for objects in objects_dirs {
+            // a missing fan-out directory just means there are no candidates
+            let entries = std::fs::read_dir(objects.join(dir));
+            for entry in entries.into_iter().flatten() {
+                let name = entry?.file_name();
+                let name = name.to_string_lossy();
+                if name.starts_with(rest) {
+                    matches.push(format!("{dir}{name}"));
+                }
+            }
+            for path in Object::pack_indexes(&objects)? {
+                let index = Object::pack_index(&path)?;
+                matches.extend(
+                    index
+                        .entries
+                        .iter()
+                        .map(|entry| hex::encode(entry.hash))
+                        .filter(|hex| hex.starts_with(&prefix)),
+                );
+            }
+        }
+        // an object may be both loose and packed
+        matches.sort();
+        matches.dedup();
+        match matches.len() {
+            0 => Err(GitError::ObjectNotFound(prefix)),
+            1 => Ok(matches.remove(0)),
+            _ => Err(GitError::AmbiguousObjectName(prefix)),
+        }
+    }
 
-        // read object type
-        r.read_until(b' ', &mut buf)?;
-        // ignore the last space
-        let kind = ObjectKind::try_from(&buf[..buf.len() - 1])?;
-        buf.clear();
+    /// the decompressed content of the object `hex` of `git_dir`, its `kind size\0`
+    /// header included, read without checking the header so that objects which don't
+    /// parse can still be inspected. packed objects, which can't be of an unknown kind,
+    /// get their header rebuilt.
+    pub fn load_raw_in(git_dir: &Path, hex: &str) -> Result<Vec<u8>, GitError> {
+        let id: ObjectId = match hex.len() {
+            40 => hex.parse()?,
+            _ => Object::expand_prefix_in(git_dir, hex)?.parse()?,
+        };
+        let mut loose = git_dir.join("objects").join(id.loose_path());
+        if !loose.is_file() {
+            let alternates = Object::alternates(git_dir)?.into_iter();
+            match alternates
+                .map(|d| d.join(id.loose_path()))
+                .find(|p| p.is_file())
+            {
+                Some(path) => loose = path,
+                None => {
+                    let object = Object::load_in(git_dir, &id.to_string())?;
+                    let mut raw = format!("{} {}\0", object.kind, object.body.len()).into_bytes();
+                    raw.extend(object.body);
+                    return Ok(raw);
+                }
+            }
+        }
+        let mut raw = Vec::new();
+        flate2::read::ZlibDecoder::new(std::fs::File::open(&loose)?)
+            .read_to_end(&mut raw)
+            .map_err(|e| GitError::corrupt(format!("failed to inflate {id}: {e}")))?;
+        Ok(raw)
+    }
 
-        // read object size
-        r.read_until(b'\0', &mut buf)?;
-        // ignore the last \0
-        let size: usize = str::from_utf8(&buf[..buf.len() - 1])?.parse()?;
-        buf.resize(size, 0);
+    /// opens an object of the object repository for streaming its body
+    pub fn open(hex: &str) -> Result<ObjectReader, GitError> {
+        Self::open_in(&crate::repo::git_dir(), hex)
+    }
+    /// opens an object of the object repository of `git_dir` for streaming its body.
+    /// `hex` may be abbreviated as long as it identifies a single object. loose objects
+    /// are looked up first, then the packs under `objects/pack`, then the object
+    /// directories listed in `objects/info/alternates`.
+    pub fn open_in(git_dir: &Path, hex: &str) -> Result<ObjectReader, GitError> {
+        let id = match hex.len() {
+            40 => hex.parse()?,
+            _ => Object::expand_prefix_in(git_dir, hex)?.parse()?,
+        };
+        Object::open_id_in(git_dir, &id)
+    }
+
+    /// like [`Object::open_in`], for an object named by its full `id`
+    pub fn open_id_in(git_dir: &Path, id: &ObjectId) -> Result<ObjectReader, GitError> {
+        // alternates are only read once the repository's own store comes up empty
+        if let Some(r) = Object::open_in_objects(git_dir, &git_dir.join("objects"), id)? {
+            return Ok(r);
+        }
+        for objects in Object::alternates(git_dir)? {
+            if let Some(r) = Object::open_in_objects(git_dir, &objects, id)? {
+                return Ok(r);
+            }
+        }
+        Err(GitError::ObjectNotFound(id.to_string()))
+    }
+
+    /// looks the object up loose, then packed, in the object directory `objects`
+    fn open_in_objects(
+        git_dir: &Path,
+        objects: &Path,
+        id: &ObjectId,
+    ) -> Result<Option<ObjectReader>, GitError> {
+        match std::fs::File::open(objects.join(id.loose_path())) {
+            Ok(object) => ObjectReader::new(object).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let object = Object::load_packed_in(git_dir, objects, id)?;
+                Ok(object.map(ObjectReader::from))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// the object directories `objects/info/alternates` of `git_dir` lists, and in turn
+    /// the ones theirs list, in the order they are found. relative paths are relative to
+    /// the object directory listing them. the list is read once per `git_dir`, and again
+    /// only once one of the files it was read from changes, shows up or goes away.
+    fn alternates(git_dir: &Path) -> Result<Vec<PathBuf>, GitError> {
+        /// each alternates file looked at, with its mtime, `None` if it was missing
+        type Stamps = Vec<(PathBuf, Option<SystemTime>)>;
+        type Cache = HashMap<PathBuf, (Stamps, Vec<PathBuf>)>;
+        static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let cache = CACHE.get_or_init(Default::default);
+        if let Some((stamps, dirs)) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(git_dir) {
+            if stamps.iter().all(|(path, mtime)| modified(path) == *mtime) {
+                return Ok(dirs.clone());
+            }
+        }
+
+        let primary = git_dir.join("objects");
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut stamps = Stamps::new();
+        let mut pending = VecDeque::from([(primary.clone(), 0)]);
+        while let Some((objects, depth)) = pending.pop_front() {
+            let path = objects.join("info").join("alternates");
+            // taken before reading, so that a change made in between is seen next time
+            stamps.push((path.clone(), modified(&path)));
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let lines = content.lines().map(str::trim);
+            for line in lines.filter(|l| !l.is_empty() && !l.starts_with('#')) {
+                let dir = objects.join(line);
+                if depth < MAX_ALTERNATE_DEPTH && dir != primary && !dirs.contains(&dir) {
+                    dirs.push(dir.clone());
+                    pending.push_back((dir, depth + 1));
+                }
+            }
+        }
+        cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(git_dir.to_owned(), (stamps, dirs.clone()));
+        Ok(dirs)
+    }
+
+    /// looks the object up in the packs of the object directory `objects`, `None` if
+    /// none of them holds it. delta bases may live anywhere in the store of `git_dir`.
+    fn load_packed_in(
+        git_dir: &Path,
+        objects: &Path,
+        id: &ObjectId,
+    ) -> Result<Option<Self>, GitError> {
+        for path in Object::pack_indexes(objects)? {
+            let index = Object::pack_index(&path)?;
+            let Some(offset) = index.find(id.as_bytes()) else {
+                continue;
+            };
+            let mut pack = BufReader::new(std::fs::File::open(path.with_extension("pack"))?);
+            let object = Object::read_packed_at(git_dir, &index, &mut pack, offset)?;
+            return Ok(Some(object));
+        }
+        Ok(None)
+    }
+
+    /// reads the object whose entry starts at `offset` in `pack`, indexed by `index`.
+    /// the base of an offset delta is an earlier entry of the same pack, that of a ref
+    /// delta may be anywhere in the store of `git_dir`. bases in the same pack are read
+    /// through `pack` rather than opening it again for every link of the delta chain.
+    fn read_packed_at(
+        git_dir: &Path,
+        index: &PackIndex,
+        pack: &mut BufReader<std::fs::File>,
+        offset: u64,
+    ) -> Result<Self, GitError> {
+        pack.seek(SeekFrom::Start(offset))?;
+        match read_packed_object(&mut *pack).map_err(GitError::corrupt)? {
+            PackedObject::Whole(object) => Ok(object),
+            PackedObject::RefDelta { base, delta } => {
+                let base = match index.find(&base) {
+                    Some(base_offset) => Object::read_packed_at(git_dir, index, pack, base_offset)?,
+                    None => Object::load_in(git_dir, &hex::encode(base))?,
+                };
+                apply_delta(&delta, &base).map_err(GitError::corrupt)
+            }
+            PackedObject::OfsDelta { distance, delta } => {
+                let base_offset = offset
+                    .checked_sub(distance as u64)
+                    .filter(|_| distance > 0)
+                    .ok_or_else(|| {
+                        GitError::corrupt(format!(
+                            "offset delta at {offset} has no base {distance} bytes back"
+                        ))
+                    })?;
+                let base = Object::read_packed_at(git_dir, index, pack, base_offset)?;
+                apply_delta(&delta, &base).map_err(GitError::corrupt)
+            }
+        }
+    }
+
+    /// the pack index at `path`, loaded once per process and again only if the file
+    /// changes
+    fn pack_index(path: &Path) -> Result<Arc<PackIndex>, GitError> {
+        type Cache = HashMap<PathBuf, (SystemTime, Arc<PackIndex>)>;
+        static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+        let cache = CACHE.get_or_init(Default::default);
+        let modified = std::fs::metadata(path)?.modified()?;
+        if let Some((mtime, index)) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(path) {
+            if *mtime == modified {
+                return Ok(index.clone());
+            }
+        }
+
+        let index = Arc::new(PackIndex::load(path).map_err(GitError::corrupt)?);
+        cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_owned(), (modified, index.clone()));
+        Ok(index)
+    }
+
+    /// paths of the `.idx` files under the `pack` directory of the object directory
+    /// `objects`
+    fn pack_indexes(objects: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut indexes = Vec::new();
+        let entries = std::fs::read_dir(objects.join("pack"));
+        for entry in entries.into_iter().flatten() {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "idx") {
+                indexes.push(path);
+            }
+        }
+        Ok(indexes)
+    }
 
-        r.read_exact(&mut buf)?;
+    /// creates new object from the zlib compressed byte stream of a loose object, written
+    /// at any compression level. bytes following the end of the zlib stream, such as
+    /// padding, are never read, so only the body itself must match its declared size.
+    pub fn new_object_from<R: Read>(raw: R) -> Result<Self, GitError> {
+        let mut r = BufReader::new(flate2::read::ZlibDecoder::new(raw));
+        let (kind, size) = read_header(&mut r)?;
+        let body = read_body(&mut r, size)?;
 
-        Ok(Self { kind, body: buf })
+        Ok(Self { kind, body })
+    }
+    /// decodes a loose object already held in memory, i.e. the zlib stream of its file
+    pub fn from_loose_bytes(raw: &[u8]) -> Result<Self, GitError> {
+        Self::new_object_from(std::io::Cursor::new(raw))
     }
 
     fn ensure_dir(path: &Path) -> anyhow::Result<()> {
         std::fs::create_dir_all(path)
             .context(format!("failed to create object directory for {path:?}"))
     }
-    fn path_from_hex(hex: &str) -> PathBuf {
-        let mut p = PathBuf::from(".git/objects");
-        p.push(&hex[..2]);
-        p.push(&hex[2..]);
-        p
+}
+
+/// reads the `kind size\0` header in front of a loose object's body
+fn read_header<R: BufRead>(r: &mut R) -> Result<(ObjectKind, usize), GitError> {
+    let mut buf = Vec::new();
+
+    // read object type
+    r.read_until(b' ', &mut buf).map_err(stream_error)?;
+    // ignore the last space
+    let Some(b' ') = buf.pop() else {
+        return Err(GitError::corrupt("malformed object header"));
+    };
+    let kind = ObjectKind::try_from(&buf[..])?;
+    buf.clear();
+
+    // read object size
+    r.read_until(b'\0', &mut buf).map_err(stream_error)?;
+    // ignore the last \0
+    let Some(b'\0') = buf.pop() else {
+        return Err(GitError::corrupt("malformed object header"));
+    };
+    let size: usize = str::from_utf8(&buf)
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .ok_or(GitError::corrupt("malformed object size"))?;
+
+    Ok((kind, size))
+}
+
+/// an object whose zlib stream doesn't decode is corrupt, not a failure to read it.
+/// flate2 reports a bad stream as invalid input.
+fn stream_error(e: std::io::Error) -> GitError {
+    match e.kind() {
+        std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput => GitError::corrupt(e),
+        _ => e.into(),
+    }
+}
+
+/// reads the `size` bytes of body announced by the header, making sure the object
+/// holds exactly that many
+fn read_body<R: Read>(r: &mut R, size: usize) -> Result<Vec<u8>, GitError> {
+    let corrupt = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            GitError::corrupt(format!("object truncated: expected {size} bytes"))
+        }
+        _ => stream_error(e),
+    };
+    let mut buf = vec![0; size];
+    r.read_exact(&mut buf).map_err(corrupt)?;
+    if r.read(&mut [0u8; 1]).map_err(corrupt)? != 0 {
+        return Err(GitError::corrupt(format!(
+            "object is larger than its declared {size} bytes"
+        )));
+    }
+    Ok(buf)
+}
+
+/// gives access to an object's header up front and streams its body through `Read`.
+/// loose objects are decompressed lazily.
+pub struct ObjectReader {
+    pub kind: ObjectKind,
+    pub size: usize,
+    body: Box<dyn Read>,
+    /// bytes of the body not read yet
+    remaining: usize,
+}
+
+impl ObjectReader {
+    /// reads the header of the zlib compressed loose object `raw`
+    pub fn new<R: Read + 'static>(raw: R) -> Result<Self, GitError> {
+        let mut r = BufReader::new(flate2::read::ZlibDecoder::new(raw));
+        let (kind, size) = read_header(&mut r)?;
+
+        Ok(Self {
+            kind,
+            size,
+            body: Box::new(r),
+            remaining: size,
+        })
+    }
+}
+
+impl From<Object> for ObjectReader {
+    fn from(object: Object) -> Self {
+        Self {
+            kind: object.kind,
+            size: object.body.len(),
+            remaining: object.body.len(),
+            body: Box::new(std::io::Cursor::new(object.body)),
+        }
     }
-    fn path(hash: &[u8]) -> PathBuf {
-        Self::path_from_hex(&hex::encode(hash))
+}
+
+impl Read for ObjectReader {
+    /// fails if the body turns out shorter or longer than the header announced
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 {
+            return match self.body.read(&mut [0u8; 1])? {
+                0 => Ok(0),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("object is larger than its declared {} bytes", self.size),
+                )),
+            };
+        }
+        let len = buf.len().min(self.remaining);
+        let n = self.body.read(&mut buf[..len])?;
+        if n == 0 && len != 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("object truncated: expected {} bytes", self.size),
+            ));
+        }
+        self.remaining -= n;
+        Ok(n)
     }
 }
 
 impl Object {
-    pub fn new_blob_from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+    /// reads the file at `path` into a blob, normalizing its line endings as `text` says
+    pub fn new_blob_from_file<P: AsRef<Path>>(path: P, text: Text) -> anyhow::Result<Self> {
         let mut object = std::fs::File::open(path).context("failed to open the file to hash")?;
 
         let mut buf = Vec::new();
@@ -101,7 +498,15 @@ impl Object {
             .context("failed to read from the file")?;
         Ok(Self {
             kind: ObjectKind::Blob,
-            body: buf,
+            body: normalize_eol(buf, text),
+        })
+    }
+    /// builds the blob of the symlink at `path`, which holds the path it points to
+    pub fn new_blob_from_symlink<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let target = std::fs::read_link(path).context("failed to read the symlink to hash")?;
+        Ok(Self {
+            kind: ObjectKind::Blob,
+            body: target.into_os_string().into_encoded_bytes(),
         })
     }
     pub fn new(kind: ObjectKind, body: Vec<u8>) -> Self {
@@ -109,11 +514,12 @@ impl Object {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ObjectKind {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl Display for ObjectKind {
@@ -122,20 +528,25 @@ impl Display for ObjectKind {
             ObjectKind::Blob => "blob",
             ObjectKind::Tree => "tree",
             ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
         };
         write!(f, "{display}")
     }
 }
 
 impl TryFrom<&[u8]> for ObjectKind {
-    type Error = anyhow::Error;
+    type Error = GitError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        match str::from_utf8(value)? {
-            "blob" => Ok(Self::Blob),
-            "tree" => Ok(Self::Tree),
-            "commit" => Ok(Self::Commit),
-            kind => anyhow::bail!("unknown object format! [{kind}]"),
+        match value {
+            b"blob" => Ok(Self::Blob),
+            b"tree" => Ok(Self::Tree),
+            b"commit" => Ok(Self::Commit),
+            b"tag" => Ok(Self::Tag),
+            kind => Err(GitError::corrupt(format!(
+                "unknown object format! [{}]",
+                String::from_utf8_lossy(kind)
+            ))),
         }
     }
 }
@@ -145,43 +556,275 @@ pub struct Tree {
     pub nodes: Vec<Node>,
 }
 
+impl Tree {
+    /// serializes the tree into a tree object, with the entries in git's order. each
+    /// mode is written as it was read, so an unmodified tree keeps its hash.
+    pub fn to_object(&self) -> Object {
+        let mut nodes: Vec<&Node> = self.nodes.iter().collect();
+        nodes.sort_by_cached_key(|node| {
+            let dir = matches!(node.kind, NodeKind::Dir { .. });
+            entry_order_key(node.name.as_bytes(), dir)
+        });
+        let mut body = Vec::new();
+        for node in nodes {
+            body.extend(format!("{} {}\0", node.raw_mode, node.name).as_bytes());
+            body.extend(node.hash);
+        }
+        Object::new(ObjectKind::Tree, body)
+    }
+}
+
+/// whether `name` can name a tree entry. anything that would lead a checkout out of
+/// the directory of the tree, or into the repository itself, is refused like git does.
+fn is_valid_entry_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..")
+        && !name.eq_ignore_ascii_case(".git")
+        && !name.contains(['/', '\0'])
+}
+
+/// git orders tree entries as if directory names ended with a `/`
+fn entry_order_key(name: &[u8], dir: bool) -> Vec<u8> {
+    let mut key = name.to_vec();
+    if dir {
+        key.push(b'/');
+    }
+    key
+}
+
 impl TryFrom<Object> for Tree {
-    type Error = anyhow::Error;
+    type Error = GitError;
 
     fn try_from(value: Object) -> Result<Self, Self::Error> {
+        let ObjectKind::Tree = value.kind else {
+            return Err(GitError::UnexpectedKind {
+                expected: ObjectKind::Tree,
+                found: value.kind,
+            });
+        };
         let obj = value;
         let mut nodes = Vec::new();
         let mut ptr = 0;
         while let Some(mode_end_index) = obj.body[ptr..].iter().position(|c| *c == b' ') {
-            let mode = str::from_utf8(&obj.body[ptr..ptr + mode_end_index])?;
+            let mode =
+                str::from_utf8(&obj.body[ptr..ptr + mode_end_index]).map_err(GitError::corrupt)?;
             ptr += mode_end_index + 1;
 
             if let Some(name_end_index) = obj.body[ptr..].iter().position(|c| *c == b'\0') {
-                let name = str::from_utf8(&obj.body[ptr..ptr + name_end_index])?.into();
+                let name: String = str::from_utf8(&obj.body[ptr..ptr + name_end_index])
+                    .map_err(GitError::corrupt)?
+                    .into();
+                if !is_valid_entry_name(&name) {
+                    return Err(GitError::corrupt(format!(
+                        "invalid tree entry name {name:?}"
+                    )));
+                }
                 ptr += name_end_index + 1;
-                let mut hash = [0u8; 20];
-                hash.copy_from_slice(&obj.body[ptr..ptr + 20]);
+                let hash: [u8; 20] = obj
+                    .body
+                    .get(ptr..ptr + 20)
+                    .and_then(|h| h.try_into().ok())
+                    .ok_or(GitError::corrupt(format!("tree entry {name} is truncated")))?;
                 ptr += 20;
-                let kind = match mode {
-                    "40000" => NodeKind::Dir { mode: 0o40000 },
-                    "120000" => NodeKind::SymLink { mode: 0o120000 },
-                    "100644" => NodeKind::File { mode: 0o100644 },
-                    "100755" => NodeKind::File { mode: 0o100755 },
-                    _ => anyhow::bail!("malformed tree node"),
-                };
-                nodes.push(Node { kind, name, hash });
+                let kind = NodeKind::from_mode(mode).ok_or(GitError::corrupt(format!(
+                    "malformed tree node mode {mode}"
+                )))?;
+                nodes.push(Node {
+                    kind,
+                    raw_mode: mode.into(),
+                    name,
+                    hash,
+                });
             } else {
-                panic!("malformed tree");
+                return Err(GitError::corrupt("malformed tree"));
             }
         }
+        if ptr != obj.body.len() {
+            return Err(GitError::corrupt("malformed tree"));
+        }
         Ok(Self { nodes })
     }
 }
 
+/// lists the entries like `ls-tree` does, a line each
+impl Display for Tree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for node in &self.nodes {
+            writeln!(f, "{node}")?;
+        }
+        Ok(())
+    }
+}
+
+/// assembles a tree object out of its entries, ordered and laid out the way git does
+#[derive(Debug, Default)]
+pub struct TreeBuilder {
+    entries: Vec<(u32, Vec<u8>, [u8; 20])>,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// adds the entry `name` pointing at `hash`, with a git `mode` such as `0o100644`
+    pub fn add(&mut self, mode: u32, name: impl AsRef<[u8]>, hash: [u8; 20]) {
+        self.entries.push((mode, name.as_ref().to_vec(), hash))
+    }
+
+    pub fn build(mut self) -> Object {
+        self.entries
+            .sort_by_cached_key(|(mode, name, _)| entry_order_key(name, *mode == 0o40000));
+        let mut body = Vec::new();
+        for (mode, name, hash) in self.entries {
+            body.extend(format!("{mode:o} ").as_bytes());
+            body.extend(name);
+            body.push(b'\0');
+            body.extend(hash);
+        }
+        Object::new(ObjectKind::Tree, body)
+    }
+}
+
+#[derive(Debug)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl TryFrom<Object> for Commit {
+    type Error = GitError;
+
+    fn try_from(value: Object) -> Result<Self, Self::Error> {
+        let ObjectKind::Commit = value.kind else {
+            return Err(GitError::UnexpectedKind {
+                expected: ObjectKind::Commit,
+                found: value.kind,
+            });
+        };
+        let body = str::from_utf8(&value.body).map_err(GitError::corrupt)?;
+        let (headers, message) = body.split_once("\n\n").unwrap_or((body, ""));
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        for line in headers.lines() {
+            // continuation lines of multi-line headers such as gpgsig start with a space
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "tree" => tree = Some(value.to_owned()),
+                "parent" => parents.push(value.to_owned()),
+                "author" => author = Some(value.parse().map_err(GitError::corrupt)?),
+                "committer" => committer = Some(value.parse().map_err(GitError::corrupt)?),
+                _ => (),
+            }
+        }
+        Ok(Self {
+            tree: tree.ok_or(GitError::corrupt("malformed commit: missing tree"))?,
+            parents,
+            author: author.ok_or(GitError::corrupt("malformed commit: missing author"))?,
+            committer: committer.ok_or(GitError::corrupt("malformed commit: missing committer"))?,
+            message: message.to_owned(),
+        })
+    }
+}
+
+/// the headers and message of the commit, as stored in its object
+impl Display for Commit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "tree {}", self.tree)?;
+        for parent in &self.parents {
+            writeln!(f, "parent {parent}")?;
+        }
+        writeln!(f, "author {}", self.author)?;
+        writeln!(f, "committer {}", self.committer)?;
+        write!(f, "\n{}", self.message)
+    }
+}
+
+/// an annotated tag
+#[derive(Debug)]
+pub struct Tag {
+    /// hash of the tagged object
+    pub object: String,
+    /// kind of the tagged object
+    pub kind: ObjectKind,
+    pub tag: String,
+    /// very old tags were written without a tagger
+    pub tagger: Option<Signature>,
+    pub message: String,
+}
+
+impl Tag {
+    /// serializes the tag into a tag object
+    pub fn to_object(&self) -> Object {
+        Object::new(ObjectKind::Tag, self.to_string().into_bytes())
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "object {}", self.object)?;
+        writeln!(f, "type {}", self.kind)?;
+        writeln!(f, "tag {}", self.tag)?;
+        if let Some(tagger) = &self.tagger {
+            writeln!(f, "tagger {tagger}")?;
+        }
+        write!(f, "\n{}", self.message)
+    }
+}
+
+impl TryFrom<Object> for Tag {
+    type Error = GitError;
+
+    fn try_from(value: Object) -> Result<Self, Self::Error> {
+        let ObjectKind::Tag = value.kind else {
+            return Err(GitError::UnexpectedKind {
+                expected: ObjectKind::Tag,
+                found: value.kind,
+            });
+        };
+        let body = str::from_utf8(&value.body).map_err(GitError::corrupt)?;
+        let (headers, message) = body.split_once("\n\n").unwrap_or((body, ""));
+
+        let mut object = None;
+        let mut kind = None;
+        let mut tag = None;
+        let mut tagger = None;
+        for line in headers.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "object" => object = Some(value.to_owned()),
+                "type" => kind = Some(ObjectKind::try_from(value.as_bytes())?),
+                "tag" => tag = Some(value.to_owned()),
+                "tagger" => tagger = Some(value.parse().map_err(GitError::corrupt)?),
+                _ => (),
+            }
+        }
+        Ok(Self {
+            object: object.ok_or(GitError::corrupt("malformed tag: missing object"))?,
+            kind: kind.ok_or(GitError::corrupt("malformed tag: missing type"))?,
+            tag: tag.ok_or(GitError::corrupt("malformed tag: missing tag name"))?,
+            tagger,
+            message: message.to_owned(),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Node {
     pub name: String,
     pub kind: NodeKind,
+    /// the mode exactly as the tree spells it, which old trees may do in non-canonical
+    /// ways such as `100664` or a zero-padded `040000`
+    pub raw_mode: String,
     pub hash: [u8; 20],
 }
 
@@ -192,10 +835,480 @@ pub enum NodeKind {
     SymLink { mode: u32 },
 }
 
+/// the `<mode> <type> <hash>\t<name>` line `ls-tree` shows for the entry, with the
+/// canonical mode
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            NodeKind::Dir { .. } => ObjectKind::Tree,
+            _ => ObjectKind::Blob,
+        };
+        write!(
+            f,
+            "{:06o} {kind} {}\t{}",
+            self.kind.mode(),
+            hex::encode(self.hash),
+            self.name
+        )
+    }
+}
+
 impl NodeKind {
+    /// parses an octal tree entry mode, canonicalizing it like git does: files are
+    /// `100755` if the owner may execute them and `100644` otherwise
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        if mode.is_empty() {
+            return None;
+        }
+        let mode = u32::from_str_radix(mode, 8).ok()?;
+        match mode & 0o170000 {
+            0o040000 => Some(NodeKind::Dir { mode: 0o40000 }),
+            0o120000 => Some(NodeKind::SymLink { mode: 0o120000 }),
+            0o100000 if mode & 0o100 != 0 => Some(NodeKind::File { mode: 0o100755 }),
+            0o100000 => Some(NodeKind::File { mode: 0o100644 }),
+            _ => None,
+        }
+    }
+
     pub fn mode(&self) -> u32 {
         match self {
             NodeKind::Dir { mode } | NodeKind::File { mode } | NodeKind::SymLink { mode } => *mode,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(body: &[u8]) -> Object {
+        Object {
+            kind: ObjectKind::Tree,
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_tree_entries() {
+        let mut body = b"100644 a.txt\0".to_vec();
+        body.extend([0xab; 20]);
+        assert_eq!(Tree::try_from(tree(&body)).unwrap().nodes.len(), 1);
+
+        for len in [body.len() - 1, b"100644 a.txt\0".len(), 3] {
+            let err = Tree::try_from(tree(&body[..len])).unwrap_err();
+            assert!(matches!(err, GitError::Corrupt(_)), "{err}");
+        }
+    }
+
+    fn blob(body: &[u8]) -> Object {
+        Object::new(ObjectKind::Blob, body.to_vec())
+    }
+
+    /// the loose object file holding `raw`, header included
+    fn deflate(raw: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(raw).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn expands_unique_prefixes_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path();
+        // these two hash to 6bb2f98f... and 6bb2f4ee...
+        blob(b"195\n").persist_in(git_dir).unwrap();
+        blob(b"389\n").persist_in(git_dir).unwrap();
+
+        let expanded = Object::expand_prefix_in(git_dir, "6bb2F9").unwrap();
+        assert_eq!(expanded, "6bb2f98fb0227744dff2c9023c2a8d53cc721588");
+        assert_eq!(Object::load_in(git_dir, "6bb2f4").unwrap().body, b"389\n");
+        assert!(matches!(
+            Object::expand_prefix_in(git_dir, "6bb2"),
+            Err(GitError::AmbiguousObjectName(_))
+        ));
+        assert!(matches!(
+            Object::load_in(git_dir, "6bb3"),
+            Err(GitError::ObjectNotFound(_))
+        ));
+        assert!(matches!(
+            Object::expand_prefix_in(git_dir, "6bb"),
+            Err(GitError::InvalidObjectName(_))
+        ));
+    }
+
+    #[test]
+    fn round_trips_at_every_compression_level() {
+        let tmp = tempfile::tempdir().unwrap();
+        let body = b"some text that compresses, some text that compresses\n".repeat(20);
+        let object = blob(&body);
+        let path = tmp.path().join("objects").join(object.id().loose_path());
+        let mut sizes = Vec::new();
+        for level in [0, 1, 6, 9] {
+            let level = flate2::Compression::new(level);
+            let hash = object.persist_with(tmp.path(), level).unwrap();
+            assert_eq!(hash, object.hash());
+            sizes.push(std::fs::metadata(&path).unwrap().len());
+            let loaded = Object::load_in(tmp.path(), &hex::encode(hash)).unwrap();
+            assert_eq!(loaded.kind, ObjectKind::Blob);
+            assert_eq!(loaded.body, body);
+        }
+        // stored without compression the object is larger than the body
+        assert!(sizes[0] > body.len() as u64);
+        assert!(sizes[1] < body.len() as u64 / 4);
+    }
+
+    #[test]
+    fn reads_loose_objects_written_by_other_tools() {
+        let tmp = tempfile::tempdir().unwrap();
+        let object = blob(b"written elsewhere\n");
+        let path = tmp.path().join("objects").join(object.id().loose_path());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        for (level, padding) in [(1, &b""[..]), (9, &b""[..]), (6, &b"\0\0\0\0"[..])] {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+            encoder.write_all(b"blob 18\0written elsewhere\n").unwrap();
+            let mut raw = encoder.finish().unwrap();
+            raw.extend(padding);
+            std::fs::write(&path, raw).unwrap();
+            let loaded = Object::load_in(tmp.path(), &object.id().to_string()).unwrap();
+            assert_eq!(loaded.body, object.body);
+        }
+    }
+
+    #[test]
+    fn round_trips_annotated_tags() {
+        let body = "object 68293b5ced61aef70c00dd81e0efd02dc5c9126d\ntype commit\ntag v1.0\n\
+                    tagger A U Thor <author@example.com> 1112912053 +0530\n\n\
+                    first release\n\nwith notes\n";
+        let object = Object::new(ObjectKind::Tag, body.as_bytes().to_vec());
+        // as `git hash-object -t tag` names it
+        assert_eq!(
+            object.id().to_string(),
+            "4dfcdabf994a88e532a7f1d90ed4a69f7f766db5"
+        );
+
+        assert_eq!(ObjectKind::Tag.to_string(), "tag");
+        let tag = Tag::try_from(object.clone()).unwrap();
+        assert_eq!(tag.object, "68293b5ced61aef70c00dd81e0efd02dc5c9126d");
+        assert_eq!(tag.kind, ObjectKind::Commit);
+        assert_eq!(tag.tag, "v1.0");
+        assert_eq!(tag.tagger.as_ref().unwrap().name, "A U Thor");
+        assert_eq!(tag.message, "first release\n\nwith notes\n");
+        assert_eq!(tag.to_object().body, object.body);
+
+        let untagged = body.replace(
+            "tagger A U Thor <author@example.com> 1112912053 +0530\n",
+            "",
+        );
+        let tag = Tag::try_from(Object::new(ObjectKind::Tag, untagged.clone().into_bytes()));
+        let tag = tag.unwrap();
+        assert!(tag.tagger.is_none());
+        assert_eq!(tag.to_string(), untagged);
+        let missing_object = body.replace("object", "objekt");
+        assert!(Tag::try_from(Object::new(ObjectKind::Tag, missing_object.into_bytes())).is_err());
+    }
+
+    #[test]
+    fn rejects_bodies_that_do_not_match_the_declared_size() {
+        let object = Object::from_loose_bytes(&deflate(b"blob 5\0hello")).unwrap();
+        assert_eq!(object.body, b"hello");
+
+        let err = Object::from_loose_bytes(&deflate(b"blob 9\0hello")).unwrap_err();
+        assert!(
+            err.to_string().contains("truncated: expected 9 bytes"),
+            "{err}"
+        );
+        let err = Object::from_loose_bytes(&deflate(b"blob 3\0hello")).unwrap_err();
+        assert!(
+            err.to_string().contains("larger than its declared 3"),
+            "{err}"
+        );
+        for header in [&b"blob x\0hello"[..], b"blob -1\0", b"blob 5hello"] {
+            let err = Object::from_loose_bytes(&deflate(header)).unwrap_err();
+            assert!(matches!(err, GitError::Corrupt(_)), "{err}");
+        }
+    }
+
+    fn hash_of(hex: &str) -> [u8; 20] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn builds_trees_that_round_trip() {
+        let mut builder = TreeBuilder::new();
+        builder.add(
+            0o100644,
+            "foo0",
+            hash_of("f2ad6c76f0115a6ba5b00456a849810e7ec0af20"),
+        );
+        builder.add(
+            0o40000,
+            "foo",
+            hash_of("108aabee1ecf7ab27858b9b94edb90863ce0f006"),
+        );
+        builder.add(
+            0o100644,
+            "foo.txt",
+            hash_of("78981922613b2afb6025042ff6bd878ac1994e85"),
+        );
+        builder.add(
+            0o100644,
+            "foo-bar",
+            hash_of("61780798228d17af2d34fce4cfbdf35556832472"),
+        );
+        let object = builder.build();
+        // as `git write-tree` names the same entries
+        assert_eq!(
+            object.id().to_string(),
+            "8739353a0f415c5e821c480c052b54c9b3d4f033"
+        );
+
+        let tree = Tree::try_from(object.clone()).unwrap();
+        let entries: Vec<_> = tree
+            .nodes
+            .iter()
+            .map(|n| (n.raw_mode.as_str(), n.name.as_str(), n.kind.mode()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("100644", "foo-bar", 0o100644),
+                ("100644", "foo.txt", 0o100644),
+                ("40000", "foo", 0o40000),
+                ("100644", "foo0", 0o100644),
+            ]
+        );
+        let mut rebuilt = TreeBuilder::new();
+        for node in &tree.nodes {
+            rebuilt.add(node.kind.mode(), &node.name, node.hash);
+        }
+        assert_eq!(rebuilt.build().body, object.body);
+    }
+
+    #[test]
+    fn decodes_loose_objects_written_by_git() {
+        let blob = Object::from_loose_bytes(include_bytes!("../tests/fixtures/loose/blob"));
+        let blob = blob.unwrap();
+        assert_eq!(blob.kind, ObjectKind::Blob);
+        assert_eq!(blob.body, b"hello\nworld\n");
+        assert_eq!(
+            blob.id().to_string(),
+            "94954abda49de8615a048f8d2e64b5de848e27a1"
+        );
+
+        let tree = Object::from_loose_bytes(include_bytes!("../tests/fixtures/loose/tree"));
+        let tree = tree.unwrap();
+        assert_eq!(
+            tree.id().to_string(),
+            "082e0eaaf2f05e1da587a7634f881b29beb8d00d"
+        );
+        let nodes = Tree::try_from(tree).unwrap().nodes;
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(
+            (nodes[0].name.as_str(), nodes[0].hash),
+            ("README", blob.hash())
+        );
+        assert!(matches!(nodes[1].kind, NodeKind::Dir { .. }));
+        assert_eq!(nodes[1].name, "src");
+
+        let commit = Object::from_loose_bytes(include_bytes!("../tests/fixtures/loose/commit"));
+        let commit = commit.unwrap();
+        assert_eq!(
+            commit.id().to_string(),
+            "e14c4d338a2f1d601d6fcfd7752d9af3c726c943"
+        );
+        let commit = Commit::try_from(commit).unwrap();
+        assert_eq!(commit.tree, "388b56757fd0b8a1ccb45194082a2d705986c527");
+        assert_eq!(commit.parents, ["04d46590411d266e2c6312fb8b5eba35d1973522"]);
+        assert_eq!(commit.author.email, "author@example.com");
+        assert_eq!(commit.committer.time, 1112911993);
+        assert_eq!(commit.message, "second\n");
+
+        assert!(Object::from_loose_bytes(b"not zlib").is_err());
+    }
+
+    #[test]
+    fn detects_flipped_bytes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let object = blob(b"precious content\n");
+        let hex = hex::encode(object.persist_in(tmp.path()).unwrap());
+        let path = tmp.path().join("objects").join(object.id().loose_path());
+
+        // a flip inside the body, with the file otherwise well formed
+        std::fs::write(&path, deflate(b"blob 17\0precious c0ntent\n")).unwrap();
+        let err = Object::load_in(tmp.path(), &hex).unwrap_err();
+        assert!(err.to_string().contains("hash mismatch"), "{err}");
+        // looked up by prefix, the full hash is checked all the same
+        assert!(Object::load_in(tmp.path(), &hex[..8]).is_err());
+
+        // a flip in the compressed stream
+        let mut raw = deflate(b"blob 17\0precious content\n");
+        let middle = raw.len() / 2;
+        raw[middle] ^= 0x01;
+        std::fs::write(&path, raw).unwrap();
+        let err = Object::load_in(tmp.path(), &hex).unwrap_err();
+        assert!(matches!(err, GitError::Corrupt(_)), "{err}");
+    }
+
+    #[test]
+    fn keeps_nonstandard_modes_byte_for_byte() {
+        let file = hash_of("94954abda49de8615a048f8d2e64b5de848e27a1");
+        let dir = hash_of("108aabee1ecf7ab27858b9b94edb90863ce0f006");
+        let mut body = b"100664 group-writable\0".to_vec();
+        body.extend(file);
+        body.extend(b"040000 padded\0");
+        body.extend(dir);
+        let object = tree(&body);
+
+        let parsed = Tree::try_from(object.clone()).unwrap();
+        let modes: Vec<_> = parsed
+            .nodes
+            .iter()
+            .map(|n| (n.raw_mode.as_str(), n.kind.mode()))
+            .collect();
+        assert_eq!(modes, [("100664", 0o100644), ("040000", 0o40000)]);
+        let written = parsed.to_object();
+        assert_eq!(written.body, body);
+        assert_eq!(written.hash(), object.hash());
+    }
+
+    #[test]
+    fn serializes_trees_back_to_the_same_hash() {
+        let object = Object::from_loose_bytes(include_bytes!("../tests/fixtures/loose/tree"));
+        let object = object.unwrap();
+        let mut tree = Tree::try_from(object.clone()).unwrap();
+        assert_eq!(tree.to_object().id(), object.id());
+
+        // nodes out of order are written in git's order all the same
+        tree.nodes.reverse();
+        assert_eq!(tree.nodes[0].name, "src");
+        let written = tree.to_object();
+        assert_eq!(written.body, object.body);
+        assert_eq!(
+            written.id().to_string(),
+            "082e0eaaf2f05e1da587a7634f881b29beb8d00d"
+        );
+    }
+
+    #[test]
+    fn displays_trees_like_ls_tree() {
+        let mut builder = TreeBuilder::new();
+        let file = hash_of("94954abda49de8615a048f8d2e64b5de848e27a1");
+        let dir = hash_of("108aabee1ecf7ab27858b9b94edb90863ce0f006");
+        builder.add(0o100755, "run.sh", file);
+        builder.add(0o120000, "link", file);
+        builder.add(0o40000, "src", dir);
+        builder.add(0o100644, "README", file);
+        let listing = Tree::try_from(builder.build()).unwrap();
+        assert_eq!(
+            listing.to_string(),
+            "100644 blob 94954abda49de8615a048f8d2e64b5de848e27a1\tREADME\n\
+             120000 blob 94954abda49de8615a048f8d2e64b5de848e27a1\tlink\n\
+             100755 blob 94954abda49de8615a048f8d2e64b5de848e27a1\trun.sh\n\
+             040000 tree 108aabee1ecf7ab27858b9b94edb90863ce0f006\tsrc\n"
+        );
+
+        // the line shows the canonical mode, whatever the tree spells
+        let mut body = b"100664 notes\0".to_vec();
+        body.extend(file);
+        let nonstandard = Tree::try_from(tree(&body)).unwrap();
+        assert_eq!(
+            nonstandard.nodes[0].to_string(),
+            "100644 blob 94954abda49de8615a048f8d2e64b5de848e27a1\tnotes"
+        );
+    }
+
+    #[test]
+    fn finds_objects_only_present_in_alternates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path().join("repo.git");
+        let shared = tmp.path().join("shared.git");
+        let nested = tmp.path().join("nested.git");
+        let own = blob(b"own\n");
+        own.persist_in(&git_dir).unwrap();
+        // a loose object in an alternate, and packed ones in the alternate it lists
+        let loose = blob(b"shared\n");
+        loose.persist_in(&shared).unwrap();
+        let pack = nested.join("objects/pack");
+        std::fs::create_dir_all(&pack).unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        std::fs::copy(fixtures.join("ofs-delta.pack"), pack.join("pack-1.pack")).unwrap();
+        std::fs::copy(fixtures.join("ofs-delta.idx"), pack.join("pack-1.idx")).unwrap();
+        std::fs::create_dir_all(git_dir.join("objects/info")).unwrap();
+        std::fs::write(
+            git_dir.join("objects/info/alternates"),
+            "# shared store\n../../shared.git/objects\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(shared.join("objects/info")).unwrap();
+        std::fs::write(
+            shared.join("objects/info/alternates"),
+            format!("{}\n", nested.join("objects").display()),
+        )
+        .unwrap();
+
+        let load = |hex: &str| Object::load_in(&git_dir, hex).unwrap();
+        assert_eq!(load(&own.id().to_string()).body, own.body);
+        let hex = loose.id().to_string();
+        assert_eq!(load(&hex).body, loose.body);
+        assert_eq!(load(&hex[..7]).body, loose.body);
+        let raw = Object::load_raw_in(&git_dir, &hex).unwrap();
+        assert_eq!(raw, b"blob 7\0shared\n");
+
+        let index = PackIndex::load(&pack.join("pack-1.idx")).unwrap();
+        for entry in &index.entries {
+            let object = load(&hex::encode(entry.hash));
+            assert_eq!(object.hash(), entry.hash);
+        }
+        // the alternates don't see the repository's own objects
+        let err = Object::load_in(&shared, &own.id().to_string()).unwrap_err();
+        assert!(matches!(err, GitError::ObjectNotFound(_)), "{err}");
+        let missing = blob(b"nowhere\n").id().to_string();
+        let err = Object::load_in(&git_dir, &missing).unwrap_err();
+        assert!(matches!(err, GitError::ObjectNotFound(_)), "{err}");
+
+        // changes to the list are picked up by the same process. the mtimes are set so
+        // that they differ whatever the timestamp granularity of the filesystem.
+        let alternates = git_dir.join("objects/info/alternates");
+        let mtime = std::fs::metadata(&alternates).unwrap().modified().unwrap();
+        let rewrite = |content: &str, secs: u64| {
+            std::fs::write(&alternates, content).unwrap();
+            let file = std::fs::File::options()
+                .write(true)
+                .open(&alternates)
+                .unwrap();
+            file.set_modified(mtime + std::time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        rewrite("", 1);
+        let err = Object::load_in(&git_dir, &hex).unwrap_err();
+        assert!(matches!(err, GitError::ObjectNotFound(_)), "{err}");
+        rewrite("../../shared.git/objects\n", 2);
+        assert_eq!(load(&hex).body, loose.body);
+    }
+
+    #[test]
+    fn rejects_entry_names_that_leave_the_tree() {
+        let hash = hash_of("94954abda49de8615a048f8d2e64b5de848e27a1");
+        for name in [
+            "",
+            ".",
+            "..",
+            ".git",
+            ".GIT",
+            "../escaped",
+            "/etc/passwd",
+            "a/b",
+        ] {
+            let mut body = format!("100644 {name}\0").into_bytes();
+            body.extend(hash);
+            let err = Tree::try_from(tree(&body)).unwrap_err();
+            assert!(matches!(err, GitError::Corrupt(_)), "{name:?}: {err}");
+        }
+        for name in ["...", ".gitignore", "git", "..a"] {
+            let mut body = format!("100644 {name}\0").into_bytes();
+            body.extend(hash);
+            assert!(Tree::try_from(tree(&body)).is_ok(), "{name:?}");
+        }
+    }
+}
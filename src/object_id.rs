@@ -0,0 +1,60 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use crate::GitError;
+
+/// the SHA-1 name of an object. parsing one from its hex form checks that it is a full,
+/// well formed hash, so nothing downstream has to slice unchecked user input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId([u8; 20]);
+
+impl ObjectId {
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// where the object is stored loose, relative to the object directory
+    pub fn loose_path(&self) -> PathBuf {
+        let hex = self.to_string();
+        [&hex[..2], &hex[2..]].iter().collect()
+    }
+}
+
+impl From<[u8; 20]> for ObjectId {
+    fn from(hash: [u8; 20]) -> Self {
+        Self(hash)
+    }
+}
+
+impl From<ObjectId> for [u8; 20] {
+    fn from(id: ObjectId) -> Self {
+        id.0
+    }
+}
+
+/// accepts the 40 hex digits of a hash, in either case
+impl FromStr for ObjectId {
+    type Err = GitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hash = [0u8; 20];
+        match s.len() == 40 && hex::decode_to_slice(s, &mut hash).is_ok() {
+            true => Ok(Self(hash)),
+            false => Err(GitError::InvalidObjectName(s.into())),
+        }
+    }
+}
+
+impl TryFrom<&str> for ObjectId {
+    type Error = GitError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// the lowercase hex form of the hash
+impl Display for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
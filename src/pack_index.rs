@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Context};
+use sha1::Digest;
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::Packet;
+
+const SIGNATURE: &[u8; 4] = b"\xfftOc";
+const VERSION: u32 = 2;
+/// signature, version and the fanout table
+const HEADER_SIZE: usize = 8 + 256 * 4;
+/// offsets that don't fit in 31 bits are moved to a separate table of 64-bit offsets
+const LARGE_OFFSET: u64 = 0x8000_0000;
+
+/// a version 2 pack index (`.idx`), mapping object hashes to their offset in a pack
+#[derive(Debug, Default)]
+pub struct PackIndex {
+    /// entries sorted by hash
+    pub entries: Vec<PackIndexEntry>,
+    /// the SHA-1 trailer of the pack this index belongs to
+    pub pack_checksum: [u8; 20],
+}
+
+#[derive(Debug, Clone)]
+pub struct PackIndexEntry {
+    pub hash: [u8; 20],
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+impl PackIndex {
+    /// builds the index of an already parsed pack
+    pub fn from_packet(packet: &Packet) -> Self {
+        let mut entries: Vec<_> = packet
+            .entries
+            .iter()
+            .map(|entry| PackIndexEntry {
+                hash: entry.hash,
+                crc32: entry.crc32,
+                offset: entry.offset as u64,
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.hash);
+        Self {
+            entries,
+            pack_checksum: packet.checksum,
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let raw = std::fs::read(path).context(format!("failed to read the pack index {path:?}"))?;
+        Self::try_from(raw.as_slice())
+    }
+
+    /// writes the index to `path` along with its trailing checksum
+    pub fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend(SIGNATURE);
+        buf.extend(VERSION.to_be_bytes());
+
+        // fanout[i] is the number of objects whose first byte is at most i
+        let mut fanout = [0u32; 256];
+        for entry in &self.entries {
+            fanout[entry.hash[0] as usize] += 1;
+        }
+        for i in 1..fanout.len() {
+            fanout[i] += fanout[i - 1];
+        }
+        for count in fanout {
+            buf.extend(count.to_be_bytes());
+        }
+
+        for entry in &self.entries {
+            buf.extend(entry.hash);
+        }
+        for entry in &self.entries {
+            buf.extend(entry.crc32.to_be_bytes());
+        }
+        let mut large_offsets = Vec::new();
+        for entry in &self.entries {
+            let offset = match entry.offset < LARGE_OFFSET {
+                true => entry.offset as u32,
+                false => {
+                    large_offsets.push(entry.offset);
+                    LARGE_OFFSET as u32 | (large_offsets.len() as u32 - 1)
+                }
+            };
+            buf.extend(offset.to_be_bytes());
+        }
+        for offset in large_offsets {
+            buf.extend(offset.to_be_bytes());
+        }
+
+        buf.extend(self.pack_checksum);
+        let checksum: [u8; 20] = sha1::Sha1::digest(&buf).into();
+        buf.extend(checksum);
+        std::fs::write(path, buf).context(format!("failed to write the pack index {path:?}"))
+    }
+
+    /// finds the offset of `hash` in the pack indexed by the file at `path`, reading
+    /// only the parts of the index needed instead of loading the whole of it
+    pub fn lookup(path: &Path, hash: &[u8; 20]) -> anyhow::Result<Option<u64>> {
+        let mut file =
+            std::fs::File::open(path).context(format!("failed to open the pack index {path:?}"))?;
+        let mut header = [0u8; HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        if &header[..4] != SIGNATURE || read_u32(&header, 4)? != VERSION {
+            anyhow::bail!("unsupported pack index format {path:?}");
+        }
+
+        let fanout = |i: usize| read_u32(&header, 8 + i * 4).map(|n| n as u64);
+        let num_objects = fanout(255)?;
+        let start = match hash[0] {
+            0 => 0,
+            first => fanout(first as usize - 1)?,
+        };
+        let end = fanout(hash[0] as usize)?;
+
+        let mut hashes = vec![0u8; (end.saturating_sub(start) * 20) as usize];
+        file.seek(SeekFrom::Start(HEADER_SIZE as u64 + start * 20))?;
+        file.read_exact(&mut hashes)?;
+        let Some(i) = hashes.chunks_exact(20).position(|h| h == hash) else {
+            return Ok(None);
+        };
+
+        let offsets = HEADER_SIZE as u64 + num_objects * 24;
+        let mut offset = [0u8; 4];
+        file.seek(SeekFrom::Start(offsets + (start + i as u64) * 4))?;
+        file.read_exact(&mut offset)?;
+        let offset = u32::from_be_bytes(offset) as u64;
+        if offset < LARGE_OFFSET {
+            return Ok(Some(offset));
+        }
+        let mut large_offset = [0u8; 8];
+        file.seek(SeekFrom::Start(
+            offsets + num_objects * 4 + (offset - LARGE_OFFSET) * 8,
+        ))?;
+        file.read_exact(&mut large_offset)?;
+        Ok(Some(u64::from_be_bytes(large_offset)))
+    }
+
+    /// returns the offset in the pack of the object `hash`, if the pack holds it
+    pub fn find(&self, hash: &[u8; 20]) -> Option<u64> {
+        self.entries
+            .binary_search_by(|entry| entry.hash.cmp(hash))
+            .ok()
+            .map(|i| self.entries[i].offset)
+    }
+}
+
+impl TryFrom<&[u8]> for PackIndex {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: &[u8]) -> Result<Self, Self::Error> {
+        if raw.len() < HEADER_SIZE + 40 {
+            anyhow::bail!("pack index is too short");
+        }
+        let (content, checksum) = raw.split_at(raw.len() - 20);
+        if sha1::Sha1::digest(content).as_slice() != checksum {
+            anyhow::bail!("pack index checksum mismatch");
+        }
+        if &content[..4] != SIGNATURE {
+            anyhow::bail!("unsupported pack index format");
+        }
+        let version = read_u32(content, 4)?;
+        if version != VERSION {
+            anyhow::bail!("unsupported pack index version {version}");
+        }
+        let num_objects = read_u32(content, HEADER_SIZE - 4)? as usize;
+
+        let hashes = HEADER_SIZE;
+        let crcs = hashes + num_objects * 20;
+        let offsets = crcs + num_objects * 4;
+        let large_offsets = offsets + num_objects * 4;
+
+        let mut entries = Vec::with_capacity(num_objects);
+        for i in 0..num_objects {
+            let hash = content
+                .get(hashes + i * 20..hashes + (i + 1) * 20)
+                .ok_or_else(|| anyhow!("unexpected end of pack index"))?;
+            let offset = read_u32(content, offsets + i * 4)? as u64;
+            let offset = match offset < LARGE_OFFSET {
+                true => offset,
+                false => {
+                    let at = large_offsets + (offset - LARGE_OFFSET) as usize * 8;
+                    let bytes = content
+                        .get(at..at + 8)
+                        .ok_or_else(|| anyhow!("unexpected end of pack index"))?;
+                    u64::from_be_bytes(bytes.try_into()?)
+                }
+            };
+            entries.push(PackIndexEntry {
+                hash: hash.try_into()?,
+                crc32: read_u32(content, crcs + i * 4)?,
+                offset,
+            });
+        }
+        Ok(Self {
+            entries,
+            pack_checksum: content[content.len() - 20..].try_into()?,
+        })
+    }
+}
+
+fn read_u32(raw: &[u8], at: usize) -> anyhow::Result<u32> {
+    let bytes = raw
+        .get(at..at + 4)
+        .ok_or_else(|| anyhow!("unexpected end of pack index"))?;
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
@@ -1,16 +1,46 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use sha1::Digest;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::io::BufRead;
 use std::io::Read;
 use std::io::Write;
 use std::str;
 
 use crate::object::Object;
-use crate::ObjectKind;
+use crate::{ObjectKind, Progress, Verbosity};
+
+/// the most entries room is made for up front. the count in the pack header comes from
+/// the remote, so beyond this the collections only grow as entries actually arrive.
+const MAX_PREALLOCATED_ENTRIES: usize = 1 << 16;
 
 #[derive(Debug)]
 pub struct Packet {
     pub objects: HashMap<[u8; 20], Object>,
+    /// per-object metadata, in the order the objects appear in the pack
+    pub entries: Vec<PackEntry>,
+    /// the SHA-1 trailer of the pack
+    pub checksum: [u8; 20],
+}
+
+/// where and how an object is stored inside a pack
+#[derive(Debug, Clone)]
+pub struct PackEntry {
+    pub hash: [u8; 20],
+    pub kind: ObjectKind,
+    /// size recorded in the entry header, i.e. the delta size for deltified objects
+    pub size: usize,
+    /// number of bytes the entry takes in the pack, header included
+    pub packed_size: usize,
+    /// offset of the entry header from the start of the pack
+    pub offset: usize,
+    /// CRC32 of the packed entry, header included, as recorded in pack indexes
+    pub crc32: u32,
+    /// the object this one is a delta against, if any
+    pub base: Option<[u8; 20]>,
+    /// length of the delta chain leading to this object, 0 for non-deltified objects
+    pub depth: usize,
 }
 
 #[derive(Debug)]
@@ -39,110 +69,249 @@ impl TryFrom<u8> for ObjectType {
     }
 }
 
-impl TryFrom<bytes::Bytes> for Packet {
-    type Error = anyhow::Error;
+impl Packet {
+    /// parses a pack, e.g. a `.pack` file, as it is read. only the unpacked objects are
+    /// held in memory, not the pack itself.
+    pub fn from_reader<R: Read>(r: R) -> anyhow::Result<Self> {
+        let mut r = std::io::BufReader::new(r);
+        let packet = Packet::from_reader_with_bases(&mut r, Verbosity::Quiet, |_| None)?;
+        anyhow::ensure!(
+            r.fill_buf()?.is_empty(),
+            "unexpected data after the pack checksum"
+        );
+        Ok(packet)
+    }
 
-    fn try_from(raw: bytes::Bytes) -> Result<Self, Self::Error> {
-        // skip till the first line
-        let pos = raw.iter().position(|c| *c == b'\n').unwrap_or_default();
+    /// parses a raw pack stream, starting at the `PACK` signature
+    pub fn from_bytes(raw: &[u8]) -> anyhow::Result<Self> {
+        Packet::from_bytes_with_progress(raw, Verbosity::Quiet)
+    }
 
-        // the last 20 bytes are for checksum
-        let raw = &raw[pos + 1..raw.len() - 20];
+    /// like [`Packet::from_bytes`], reporting the objects as they are unpacked
+    pub fn from_bytes_with_progress(raw: &[u8], verbosity: Verbosity) -> anyhow::Result<Self> {
+        Packet::from_bytes_with_bases(raw, verbosity, |_| None)
+    }
+
+    /// like [`Packet::from_bytes_with_progress`], looking up the bases of deltas that
+    /// aren't in the pack with `find_base`, e.g. in the object store for thin packs.
+    /// those bases are only used to resolve deltas and are left out of the packet.
+    pub fn from_bytes_with_bases<F>(
+        mut raw: &[u8],
+        verbosity: Verbosity,
+        find_base: F,
+    ) -> anyhow::Result<Self>
+    where
+        F: FnMut(&[u8; 20]) -> Option<Object>,
+    {
+        let packet = Packet::from_reader_with_bases(&mut raw, verbosity, find_base)?;
+        anyhow::ensure!(raw.is_empty(), "unexpected data after the pack checksum");
+        Ok(packet)
+    }
 
-        let magic_prefix = &raw[..4];
-        assert_eq!(magic_prefix, b"PACK");
+    /// like [`Packet::from_bytes_with_bases`], parsing the pack as it is read from `r`,
+    /// which is left right past the checksum at the end of the pack
+    pub fn from_reader_with_bases<R, F>(
+        r: R,
+        verbosity: Verbosity,
+        find_base: F,
+    ) -> anyhow::Result<Self>
+    where
+        R: BufRead,
+        F: FnMut(&[u8; 20]) -> Option<Object>,
+    {
+        let mut progress = None;
+        let packet = Packet::from_reader_with_callback(r, find_base, &mut |entry, _, total| {
+            progress
+                .get_or_insert_with(|| Progress::new("Unpacking objects", Some(total), verbosity))
+                .tick(|| format!("unpacked {}:\t{}", entry.kind, hex::encode(entry.hash)));
+        })?;
+        if let Some(progress) = progress {
+            progress.finish();
+        }
+        Ok(packet)
+    }
 
-        let _version = &raw[4..8];
-        let num_objects = u32::from_be_bytes(raw[8..12].try_into()?) as usize;
+    /// like [`Packet::from_reader_with_bases`], calling `on_object` instead of reporting
+    /// progress on stderr. it's called once an object is unpacked with its entry, the
+    /// number of objects unpacked so far and the number of objects in the pack.
+    pub fn from_reader_with_callback<R, F>(
+        r: R,
+        mut find_base: F,
+        on_object: &mut dyn FnMut(&PackEntry, usize, usize),
+    ) -> anyhow::Result<Self>
+    where
+        R: BufRead,
+        F: FnMut(&[u8; 20]) -> Option<Object>,
+    {
+        let mut stream = PackStream::new(r);
+        let mut header = [0u8; 12];
+        stream
+            .read_exact(&mut header)
+            .context("pack is too short")?;
+        anyhow::ensure!(
+            &header[..4] == b"PACK",
+            "expected PACK signature, got {:?}",
+            String::from_utf8_lossy(&header)
+        );
+        let _version = &header[4..8];
+        let num_objects = u32::from_be_bytes(header[8..12].try_into()?) as usize;
+        let capacity = num_objects.min(MAX_PREALLOCATED_ENTRIES);
 
         let mut packet = Packet {
-            objects: HashMap::with_capacity(num_objects),
+            objects: HashMap::with_capacity(capacity),
+            entries: Vec::with_capacity(capacity),
+            checksum: [0; 20],
         };
+        // first pass: inflate every entry, setting deltas aside until their base is known
+        let mut slots = Vec::with_capacity(capacity);
+        let mut pending: HashMap<[u8; 20], Vec<(usize, Vec<u8>)>> = HashMap::new();
+        // offset deltas, by the position of their base in `slots`
+        let mut pending_ofs: HashMap<usize, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        // positions in `slots` by pack offset, to find the bases of offset deltas
+        let mut slot_at = HashMap::new();
+        // resolved objects, along with their position and delta depth, whose dependent
+        // deltas are yet to be resolved
+        let mut resolved = Vec::new();
+        // a single decompressor, reset for each entry, inflates the whole pack
+        let mut inflater = flate2::Decompress::new(true);
+        for _ in 0..num_objects {
+            let offset = stream.offset;
+            stream.crc32 = crc32fast::Hasher::new();
+            let header = read_entry_header(&mut stream)?;
+            let (size, base) = match inflate_entry(&mut stream, &mut inflater, header)? {
+                PackedObject::Whole(obj) => {
+                    let size = obj.body.len();
+                    resolved.push((slots.len(), obj, 0));
+                    (size, None)
+                }
+                PackedObject::RefDelta { base, delta } => {
+                    let size = delta.len();
+                    pending.entry(base).or_default().push((slots.len(), delta));
+                    (size, Some(base))
+                }
+                PackedObject::OfsDelta { distance, delta } => {
+                    let size = delta.len();
+                    let base = offset
+                        .checked_sub(distance)
+                        .and_then(|base_offset| slot_at.get(&base_offset))
+                        .ok_or_else(|| {
+                            anyhow!("offset delta at {offset} has no base {distance} bytes back")
+                        })?;
+                    pending_ofs
+                        .entry(*base)
+                        .or_default()
+                        .push((slots.len(), delta));
+                    // the base hash is only known once the base is resolved
+                    (size, None)
+                }
+            };
+            slot_at.insert(offset, slots.len());
+            slots.push(Slot {
+                offset,
+                end: stream.offset,
+                crc32: std::mem::take(&mut stream.crc32).finalize(),
+                size,
+                base,
+            });
+        }
+        // the checksum covers everything up to itself
+        let checksum: [u8; 20] = stream.checksum.clone().finalize().into();
+        stream
+            .read_exact(&mut packet.checksum)
+            .context("pack is truncated, the checksum is missing")?;
+        anyhow::ensure!(packet.checksum == checksum, "pack checksum mismatch");
 
-        // header size
-        let mut ptr = 12;
-        while ptr < raw.len() {
-            let obj_type_byte = raw[ptr];
-            let obj_type = ObjectType::try_from((obj_type_byte & 0b0111_0000) >> 4)?;
-
-            let mut obj_len_byte = raw[ptr];
-            let mut obj_len = (obj_len_byte & 0b1111) as usize;
-            let mut shift_count = 4;
-            while obj_len_byte & 0b1000_0000 != 0 {
-                ptr += 1;
-                obj_len_byte = raw[ptr];
-                obj_len += ((obj_len_byte & 0b0111_1111) as usize) << shift_count;
-                shift_count += 7;
-            }
-            ptr += 1;
-
-            let (obj, nbytes) = match obj_type {
-                ObjectType::OfsDelta => unimplemented!(),
-                ObjectType::RefDelta => calculate_delta(&raw[ptr..], obj_len, &packet)?,
-                ObjectType::Commit | ObjectType::Tree | ObjectType::Blob | ObjectType::Tag => {
-                    let mut buf = Vec::new();
-
-                    let mut cursor = std::io::Cursor::new(&raw[ptr..]);
-                    let mut zlib_decoder = flate2::bufread::ZlibDecoder::new(&mut cursor);
-                    zlib_decoder.read_to_end(&mut buf)?;
-
-                    if buf.len() != obj_len {
-                        anyhow::bail!("malformed object. read {} expected {obj_len}", buf.len())
+        // second pass: walk down the delta chains starting from the whole objects, so
+        // a delta resolves wherever its base sits in the pack
+        let mut entries = vec![None; slots.len()];
+        let mut done = 0;
+        loop {
+            let Some((i, obj, depth)) = resolved.pop() else {
+                // deltas left are against objects the pack doesn't hold, as in thin packs
+                let bases: Vec<_> = pending.keys().copied().collect();
+                for base in bases {
+                    let Some(obj) = find_base(&base) else {
+                        continue;
+                    };
+                    for (j, delta) in pending.remove(&base).unwrap_or_default() {
+                        resolved.push((j, apply_delta(&delta, &obj)?, 1));
                     }
-
-                    let nbytes = cursor.position() as usize;
-                    (
-                        crate::Object {
-                            kind: crate::ObjectKind::try_from(obj_type)?,
-                            body: buf,
-                        },
-                        nbytes,
-                    )
                 }
+                match resolved.is_empty() {
+                    true => break,
+                    false => continue,
+                }
+            };
+            let hash = obj.hash();
+            for (j, delta) in pending.remove(&hash).unwrap_or_default() {
+                resolved.push((j, apply_delta(&delta, &obj)?, depth + 1));
+            }
+            for (j, delta) in pending_ofs.remove(&i).unwrap_or_default() {
+                slots[j].base = Some(hash);
+                resolved.push((j, apply_delta(&delta, &obj)?, depth + 1));
+            }
+            let slot = &slots[i];
+            let entry = PackEntry {
+                hash,
+                kind: obj.kind.clone(),
+                size: slot.size,
+                packed_size: slot.end - slot.offset,
+                offset: slot.offset,
+                crc32: slot.crc32,
+                base: slot.base,
+                depth,
             };
-            eprintln!("unpacked {}:\t{}", obj.kind, (hex::encode(&obj.hash())));
-            packet.objects.insert(obj.hash(), obj);
-            ptr += nbytes;
+            done += 1;
+            on_object(&entry, done, num_objects);
+            entries[i] = Some(entry);
+            packet.objects.insert(hash, obj);
         }
+        if let Some(base) = pending.keys().next() {
+            let unresolved: usize = pending.values().map(Vec::len).sum();
+            anyhow::bail!(
+                "{unresolved} deltas could not be resolved, base object {} is not in the pack",
+                hex::encode(base)
+            );
+        }
+        // the base of an offset delta is in the pack, but may itself be unresolved
+        if !pending_ofs.is_empty() {
+            let unresolved: usize = pending_ofs.values().map(Vec::len).sum();
+            anyhow::bail!("{unresolved} offset deltas could not be resolved");
+        }
+        packet.entries = entries.into_iter().flatten().collect();
         Ok(packet)
     }
 }
 
-fn calculate_delta(raw: &[u8], obj_len: usize, packet: &Packet) -> anyhow::Result<(Object, usize)> {
-    let base_hash = &raw[0..20];
-
-    let mut buf = Vec::new();
-    let mut cursor = std::io::Cursor::new(&raw[20..]);
-    let mut zlib_decoder = flate2::bufread::ZlibDecoder::new(&mut cursor);
-    zlib_decoder.read_to_end(&mut buf)?;
-
-    if buf.len() != obj_len {
-        anyhow::bail!("malformed object. read {} expected {obj_len}", buf.len())
-    }
-
-    let nbytes = cursor.position() as usize;
+/// where an entry sits in the pack, recorded before its object is resolved
+struct Slot {
+    offset: usize,
+    end: usize,
+    crc32: u32,
+    size: usize,
+    base: Option<[u8; 20]>,
+}
 
-    let raw = &buf[..];
+/// rebuilds an object from the (inflated) `delta` against `base_object`
+pub(crate) fn apply_delta(delta: &[u8], base_object: &Object) -> anyhow::Result<Object> {
+    let raw = delta;
+    let base = &base_object.body;
     let mut ptr = 0;
 
-    // skip the source-size bytes
-    while raw[ptr] & 0b1000_0000 != 0 {
-        ptr += 1;
+    let source_size = read_delta_size(raw, &mut ptr)?;
+    let target_size = read_delta_size(raw, &mut ptr)?;
+    if source_size != base.len() {
+        anyhow::bail!(
+            "delta expects a {source_size} bytes base, found {} bytes",
+            base.len()
+        );
     }
-    ptr += 1;
-
-    // skip the target-size bytes
-    while raw[ptr] & 0b1000_0000 != 0 {
-        ptr += 1;
-    }
-    ptr += 1;
-
-    let base_object = packet
-        .objects
-        .get(base_hash)
-        .ok_or(anyhow!("failed to find object {}", hex::encode(base_hash)))?;
 
     let mut obj_raw = Vec::new();
+    // the size comes from the pack, so a bogus one is an error rather than an abort
+    obj_raw
+        .try_reserve_exact(target_size)
+        .with_context(|| format!("delta target size {target_size} is too large"))?;
     while ptr < raw.len() {
         let instruction = raw[ptr];
         ptr += 1;
@@ -156,11 +325,12 @@ fn calculate_delta(raw: &[u8], obj_len: usize, packet: &Packet) -> anyhow::Resul
                 for _ in 0..4 {
                     let ofset_byte = if ofset_opcode % 2 == 1 {
                         ptr += 1;
-                        raw[ptr - 1]
+                        delta_byte(raw, ptr - 1)?
                     } else {
                         0
                     };
-                    ofset += (ofset_byte as usize) << shift_amount;
+                    ofset |= checked_shift(ofset_byte as usize, shift_amount)
+                        .ok_or_else(|| anyhow!("delta copy offset is too large"))?;
                     shift_amount += 8;
                     ofset_opcode >>= 1;
                 }
@@ -171,50 +341,693 @@ fn calculate_delta(raw: &[u8], obj_len: usize, packet: &Packet) -> anyhow::Resul
                 for _ in 0..3 {
                     let len_byte = if len_opcode % 2 == 1 {
                         ptr += 1;
-                        raw[ptr - 1]
+                        delta_byte(raw, ptr - 1)?
                     } else {
                         0
                     };
-                    len += (len_byte as usize) << shift_amount;
+                    len |= checked_shift(len_byte as usize, shift_amount)
+                        .ok_or_else(|| anyhow!("delta copy length is too large"))?;
                     shift_amount += 8;
                     len_opcode >>= 1;
                 }
-                obj_raw.extend(&base_object.body[ofset..ofset + len])
+                // a zero length stands for 0x10000 bytes
+                if len == 0 {
+                    len = 0x10000;
+                }
+                let copied = ofset
+                    .checked_add(len)
+                    .and_then(|end| base.get(ofset..end))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "delta copies {len} bytes at offset {ofset} out of a {} bytes base",
+                            base.len()
+                        )
+                    })?;
+                obj_raw.extend(copied)
             }
             // insert instruction
             false => {
                 let nbytes = instruction as usize;
-                obj_raw.extend(&raw[ptr..ptr + nbytes]);
+                if nbytes == 0 {
+                    anyhow::bail!("reserved delta instruction at offset {}", ptr - 1);
+                }
+                let inserted = raw.get(ptr..ptr + nbytes).ok_or_else(|| {
+                    anyhow!(
+                        "delta inserts {nbytes} bytes at offset {ptr} past its end at {}",
+                        raw.len()
+                    )
+                })?;
+                obj_raw.extend(inserted);
                 ptr += nbytes;
             }
         }
     }
-    let obj = crate::Object {
+    if obj_raw.len() != target_size {
+        anyhow::bail!(
+            "delta produced {} bytes, expected {target_size}",
+            obj_raw.len()
+        );
+    }
+    Ok(crate::Object {
         kind: base_object.kind.clone(),
         body: obj_raw,
+    })
+}
+
+fn delta_byte(raw: &[u8], at: usize) -> anyhow::Result<u8> {
+    raw.get(at)
+        .copied()
+        .ok_or_else(|| anyhow!("delta is truncated at offset {at}"))
+}
+
+/// reads one of the little-endian base-128 sizes a delta starts with
+fn read_delta_size(raw: &[u8], ptr: &mut usize) -> anyhow::Result<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = delta_byte(raw, *ptr)?;
+        *ptr += 1;
+        size |= checked_shift((byte & 0b0111_1111) as usize, shift)
+            .ok_or_else(|| anyhow!("delta size is too large"))?;
+        shift += 7;
+        if byte & 0b1000_0000 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// `value << shift`, `None` if any of its bits would be shifted out
+fn checked_shift(value: usize, shift: u32) -> Option<usize> {
+    value
+        .checked_shl(shift)
+        .filter(|shifted| shifted >> shift == value)
+}
+
+/// an object as stored in a pack, with deltas left unresolved
+pub(crate) enum PackedObject {
+    Whole(Object),
+    RefDelta {
+        base: [u8; 20],
+        delta: Vec<u8>,
+    },
+    /// a delta against the entry `distance` bytes before this one in the pack
+    OfsDelta {
+        distance: usize,
+        delta: Vec<u8>,
+    },
+}
+
+/// reads the single pack entry `r` is positioned at
+pub(crate) fn read_packed_object<R: BufRead>(mut r: R) -> anyhow::Result<PackedObject> {
+    let header = read_entry_header(&mut r)?;
+    inflate_entry(&mut r, &mut flate2::Decompress::new(true), header)
+}
+
+/// a pack being read, keeping track of the offset into it, the checksum of the bytes
+/// read so far and the CRC32 of those of the current entry
+struct PackStream<R> {
+    inner: R,
+    offset: usize,
+    checksum: sha1::Sha1,
+    crc32: crc32fast::Hasher,
+}
+
+impl<R: BufRead> PackStream<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            checksum: sha1::Sha1::new(),
+            crc32: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Read for PackStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for PackStream<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt == 0 {
+            return;
+        }
+        // the buffer is already filled, as `consume` follows `fill_buf`
+        if let Ok(buf) = self.inner.fill_buf() {
+            let consumed = &buf[..amt.min(buf.len())];
+            self.checksum.update(consumed);
+            self.crc32.update(consumed);
+        }
+        self.inner.consume(amt);
+        self.offset += amt;
+    }
+}
+
+/// what precedes the compressed data of a pack entry
+struct EntryHeader {
+    obj_type: ObjectType,
+    /// size of the inflated data
+    obj_len: usize,
+    base: DeltaBase,
+}
+
+/// where the base of a deltified entry is
+enum DeltaBase {
+    None,
+    /// the hash of the base, for a `RefDelta`
+    Ref([u8; 20]),
+    /// how many bytes before the delta entry the base entry starts, for an `OfsDelta`
+    Ofs(usize),
+}
+
+fn read_entry_header<R: Read>(mut r: R) -> anyhow::Result<EntryHeader> {
+    let mut byte = [0u8; 1];
+    r.read_exact(&mut byte)?;
+    let obj_type = ObjectType::try_from((byte[0] & 0b0111_0000) >> 4)?;
+    let mut obj_len = (byte[0] & 0b1111) as usize;
+    let mut shift_count = 4;
+    while byte[0] & 0b1000_0000 != 0 {
+        r.read_exact(&mut byte)?;
+        obj_len |= checked_shift((byte[0] & 0b0111_1111) as usize, shift_count)
+            .ok_or_else(|| anyhow!("pack entry size is too large"))?;
+        shift_count += 7;
+    }
+
+    let base = match obj_type {
+        ObjectType::RefDelta => {
+            let mut hash = [0u8; 20];
+            r.read_exact(&mut hash)?;
+            DeltaBase::Ref(hash)
+        }
+        ObjectType::OfsDelta => DeltaBase::Ofs(read_ofs_distance(&mut r)?),
+        _ => DeltaBase::None,
     };
+    Ok(EntryHeader {
+        obj_type,
+        obj_len,
+        base,
+    })
+}
 
-    Ok((obj, nbytes + 20))
+/// reads the distance back to the base of an offset delta: a big-endian base-128
+/// number where every continuation byte also adds one, so each distance has a single
+/// encoding
+fn read_ofs_distance<R: Read>(mut r: R) -> anyhow::Result<usize> {
+    let mut byte = [0u8; 1];
+    r.read_exact(&mut byte)?;
+    let mut distance = (byte[0] & 0b0111_1111) as usize;
+    while byte[0] & 0b1000_0000 != 0 {
+        r.read_exact(&mut byte)?;
+        distance = distance
+            .checked_add(1)
+            .and_then(|d| d.checked_mul(128))
+            .ok_or_else(|| anyhow!("offset delta distance is too large"))?
+            | (byte[0] & 0b0111_1111) as usize;
+    }
+    Ok(distance)
 }
 
-#[derive(Debug)]
-pub struct PacketLine {
-    pub data: Vec<u8>,
+/// inflates the data of the entry `header` was read from, which `r` is positioned at,
+/// using and resetting `inflater`. `r` is left right past the compressed data.
+fn inflate_entry<R: BufRead>(
+    r: &mut R,
+    inflater: &mut flate2::Decompress,
+    header: EntryHeader,
+) -> anyhow::Result<PackedObject> {
+    let EntryHeader {
+        obj_type,
+        obj_len,
+        base,
+    } = header;
+    let mut buf = Vec::new();
+    // the size comes from the pack, so a bogus one is an error rather than an abort
+    buf.try_reserve_exact(obj_len)
+        .with_context(|| format!("object size {obj_len} is too large"))?;
+    inflater.reset(true);
+    loop {
+        let input = r.fill_buf()?;
+        anyhow::ensure!(!input.is_empty(), "pack is truncated");
+        let (total_in, total_out) = (inflater.total_in(), inflater.total_out());
+        let status = match buf.len() < buf.capacity() {
+            true => inflater.decompress_vec(input, &mut buf, flate2::FlushDecompress::None)?,
+            // the announced size is reached, only the zlib trailer is expected to be left.
+            // any more data ends up in `buf` all the same, to report the size mismatch.
+            false => {
+                let mut spill = [0u8; 64];
+                let status =
+                    inflater.decompress(input, &mut spill, flate2::FlushDecompress::None)?;
+                buf.extend(&spill[..(inflater.total_out() - total_out) as usize]);
+                status
+            }
+        };
+        let consumed = (inflater.total_in() - total_in) as usize;
+        r.consume(consumed);
+        if status == flate2::Status::StreamEnd {
+            break;
+        }
+        anyhow::ensure!(
+            consumed > 0 || inflater.total_out() > total_out,
+            "corrupt deflate stream"
+        );
+    }
+    if buf.len() != obj_len {
+        anyhow::bail!("malformed object. read {} expected {obj_len}", buf.len())
+    }
+
+    Ok(match base {
+        DeltaBase::Ofs(distance) => PackedObject::OfsDelta {
+            distance,
+            delta: buf,
+        },
+        DeltaBase::Ref(base) => PackedObject::RefDelta { base, delta: buf },
+        DeltaBase::None => PackedObject::Whole(crate::Object {
+            kind: crate::ObjectKind::try_from(obj_type)?,
+            body: buf,
+        }),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketLine {
+    /// `0000`, terminates a message or a section of it
+    Flush,
+    /// `0001`, separates the sections of a protocol v2 message
+    Delim,
+    /// `0002`, marks the end of a protocol v2 response
+    ResponseEnd,
+    Data(Vec<u8>),
 }
 
 impl PacketLine {
-    pub fn len(&self) -> usize {
-        self.data.len()
+    /// the number of bytes the packet takes up in the stream, length prefix included
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Self::Data(data) => data.len() + 4,
+            _ => 4,
+        }
+    }
+
+    pub fn is_flush(&self) -> bool {
+        matches!(self, Self::Flush)
+    }
+
+    /// the payload of a data packet, `None` for the special ones
+    pub fn data(&self) -> Option<&[u8]> {
+        match self {
+            Self::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// reads the next packet off `r`, for streams that can't be buffered whole upfront
+    /// such as a connection to a remote that waits for the client to speak
+    pub fn read_from<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len)
+            .context("stream ended before the next packet line")?;
+        let len = usize::from_str_radix(str::from_utf8(&len)?, 16)?;
+        match len {
+            0 => Ok(Self::Flush),
+            1 => Ok(Self::Delim),
+            2 => Ok(Self::ResponseEnd),
+            3 => anyhow::bail!("invalid packet line length {len}"),
+            _ => {
+                let mut data = vec![0; len - 4];
+                r.read_exact(&mut data)
+                    .context("stream ended in the middle of a packet line")?;
+                Ok(Self::Data(data))
+            }
+        }
+    }
+
+    /// appends the packet, length prefix included, to `buf`
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Flush => buf.extend(b"0000"),
+            Self::Delim => buf.extend(b"0001"),
+            Self::ResponseEnd => buf.extend(b"0002"),
+            Self::Data(data) => {
+                let _ = write!(buf, "{:04x}", data.len() + 4);
+                buf.extend(data);
+            }
+        }
+    }
+}
+
+/// encodes a protocol v2 request running `command` with one argument per line
+pub fn encode_command<S: AsRef<str>>(command: &str, args: &[S]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    PacketLine::Data(format!("command={command}\n").into_bytes()).encode(&mut buf);
+    PacketLine::Delim.encode(&mut buf);
+    for arg in args {
+        PacketLine::Data(format!("{}\n", arg.as_ref()).into_bytes()).encode(&mut buf);
+    }
+    PacketLine::Flush.encode(&mut buf);
+    buf
+}
+
+/// capabilities this client is able to handle, in the order they are requested
+const SUPPORTED_CAPABILITIES: &[&str] = &[
+    "multi_ack_detailed",
+    "multi_ack",
+    "side-band-64k",
+    "shallow",
+    "agent=codecrafters-git",
+];
+
+/// the pack stream of a side-band multiplexed upload-pack response, extracted as it is
+/// read.
+///
+/// band 1 carries pack data, band 2 progress messages which are forwarded to stderr,
+/// and band 3 a fatal error reported by the server, which fails the read. the stream
+/// ends with a flush packet.
+pub struct SidebandReader<R> {
+    inner: R,
+    /// the pack data of the last band 1 packet, up to `pos` already read
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> SidebandReader<R> {
+    /// reads the side-band packets `inner` is positioned at
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for SidebandReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if self.done || out.is_empty() {
+                return Ok(0);
+            }
+            self.buf.clear();
+            self.pos = 0;
+            let packet_line = PacketLine::read_from(&mut self.inner)
+                .context("malformed side-band stream")
+                .map_err(std::io::Error::other)?;
+            match packet_line.data() {
+                Some(data) => {
+                    demultiplex_packet(data, &mut self.buf).map_err(std::io::Error::other)?
+                }
+                None => self.done = true,
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn demultiplex_packet(data: &[u8], pack: &mut Vec<u8>) -> anyhow::Result<()> {
+    let Some((band, payload)) = data.split_first() else {
+        return Ok(());
+    };
+    match band {
+        1 => pack.extend(payload),
+        2 => std::io::stderr().write_all(payload)?,
+        3 => anyhow::bail!(
+            "remote error: {}",
+            String::from_utf8_lossy(payload).trim_end()
+        ),
+        _ => (),
+    }
+    Ok(())
+}
+
+/// reads the response to a protocol v2 `fetch` command up to its `packfile` section,
+/// returning the shallow boundary reported in the `shallow-info` section before it
+/// along with a reader of the pack, which is always side-band multiplexed
+pub fn parse_fetch_response<R: Read>(
+    mut r: R,
+) -> anyhow::Result<(SidebandReader<R>, ShallowUpdate)> {
+    let mut shallow = ShallowUpdate::default();
+    let mut section = None;
+    loop {
+        let packet_line = PacketLine::read_from(&mut r).context("malformed fetch response")?;
+        let Some(data) = packet_line.data() else {
+            anyhow::ensure!(
+                !packet_line.is_flush(),
+                "fetch response has no packfile section"
+            );
+            // the other sections end with a delimiter
+            section = None;
+            continue;
+        };
+        let line = str::from_utf8(data)?.trim_end();
+        match section.as_deref() {
+            None if line == "packfile" => return Ok((SidebandReader::new(r), shallow)),
+            None => section = Some(line.to_string()),
+            Some("shallow-info") => {
+                anyhow::ensure!(shallow.record(line), "malformed shallow-info line [{line}]");
+            }
+            Some(_) => (),
+        }
+    }
+}
+
+/// capability list as advertised by the server or requested by the client
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    caps: Vec<String>,
+}
+
+impl Capabilities {
+    /// parses the space separated capability list that follows the `\0` of the first ref
+    pub fn parse(raw: &str) -> Self {
+        Self {
+            caps: raw.split_whitespace().map(String::from).collect(),
+        }
+    }
+
+    /// checks whether the capability `name` is present, ignoring any `=value` suffix
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// returns the value of a `name=value` capability, or `""` for valueless ones
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.caps.iter().find_map(|cap| match cap.split_once('=') {
+            Some((key, value)) if key == name => Some(value),
+            None if cap == name => Some(""),
+            _ => None,
+        })
+    }
+
+    /// intersects the advertised capabilities with the ones this client supports
+    pub fn negotiate(&self) -> Self {
+        let mut caps: Vec<String> = SUPPORTED_CAPABILITIES
+            .iter()
+            .filter(|cap| {
+                let name = cap.split_once('=').map_or(**cap, |(key, _)| key);
+                self.contains(name)
+            })
+            .map(|cap| cap.to_string())
+            .collect();
+        // multi_ack_detailed supersedes multi_ack, only one of them is requested
+        if caps.iter().any(|cap| cap == "multi_ack_detailed") {
+            caps.retain(|cap| cap != "multi_ack");
+        }
+        Self { caps }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.len() == 0
+        self.caps.is_empty()
+    }
+
+    /// parses a protocol v2 advertisement: the `version 2` line and the capabilities
+    /// that follow it, one per line, up to the first flush packet
+    pub fn parse_v2<I: IntoIterator<Item = PacketLine>>(lines: I) -> anyhow::Result<Self> {
+        let caps = lines
+            .into_iter()
+            .take_while(|p| !p.is_flush())
+            .map(|p| {
+                let data = p.data().ok_or(anyhow!(
+                    "unexpected special packet in capability advertisement"
+                ))?;
+                Ok(str::from_utf8(data)?.trim_end().to_string())
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { caps })
+    }
+
+    /// the protocol version the capabilities were advertised with
+    pub fn version(&self) -> u8 {
+        match self.caps.first() {
+            Some(first) if first == "version 2" => 2,
+            _ => 0,
+        }
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.caps.join(" "))
+    }
+}
+
+/// shallow boundary the server reports in response to a `deepen` request
+#[derive(Debug, Default)]
+pub struct ShallowUpdate {
+    pub shallow: Vec<String>,
+    pub unshallow: Vec<String>,
+}
+
+impl ShallowUpdate {
+    /// consumes the `shallow`/`unshallow` section at the start of an upload-pack response.
+    /// returns the update along with the number of bytes consumed from the stream.
+    pub fn parse(stream: &[u8]) -> anyhow::Result<(Self, usize)> {
+        let mut update = Self::default();
+        let mut ptr = 0;
+        while let Ok(packet_line) = PacketLineRef::try_from(&stream[ptr..]) {
+            let Some(data) = packet_line.data() else {
+                // the section is terminated by a flush packet, even when it's empty
+                if packet_line.is_flush() {
+                    ptr += packet_line.encoded_len();
+                }
+                break;
+            };
+            let line = str::from_utf8(data).unwrap_or_default().trim_end();
+            if !update.record(line) {
+                break;
+            }
+            ptr += packet_line.encoded_len();
+        }
+        Ok((update, ptr))
+    }
+
+    /// adds a `shallow` or `unshallow` line to the update, returns false for any other line
+    fn record(&mut self, line: &str) -> bool {
+        if let Some(hex) = line.strip_prefix("shallow ") {
+            self.shallow.push(hex.into());
+        } else if let Some(hex) = line.strip_prefix("unshallow ") {
+            self.unshallow.push(hex.into());
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+/// the server's reply to the `have` lines of a fetch request
+#[derive(Debug, Default)]
+pub struct Acknowledgments {
+    /// objects the server has in common with the client, as acknowledged by `ACK` lines
+    pub common: Vec<String>,
+    /// whether the server acknowledged with `ready` that it has found enough common
+    /// objects to send a pack, under multi_ack_detailed
+    pub ready: bool,
+}
+
+impl Acknowledgments {
+    /// consumes the `ACK`/`NAK` lines in front of the pack of an upload-pack response.
+    /// returns the acknowledgments along with the number of bytes consumed from the stream.
+    pub fn parse(stream: &[u8]) -> anyhow::Result<(Self, usize)> {
+        let mut acks = Self::default();
+        let mut ptr = 0;
+        while let Ok(packet_line) = PacketLineRef::try_from(&stream[ptr..]) {
+            let Some(data) = packet_line.data() else {
+                break;
+            };
+            let line = str::from_utf8(data).unwrap_or_default().trim_end();
+            if let Some(ack) = line.strip_prefix("ACK ") {
+                // multi_ack may follow the hash with a status. `continue` and `ready`
+                // are also sent for objects the server doesn't have, to hurry the
+                // negotiation along, so only plain and `common` acks are kept.
+                let (hex, status) = ack.split_once(' ').unwrap_or((ack, ""));
+                acks.ready |= status == "ready";
+                if matches!(status, "" | "common") && !acks.common.iter().any(|c| c == hex) {
+                    acks.common.push(hex.into());
+                }
+            } else if line != "NAK" {
+                break;
+            }
+            ptr += packet_line.encoded_len();
+        }
+        Ok((acks, ptr))
+    }
+}
+
+/// the have negotiation of a protocol v0 fetch under multi_ack_detailed, when every
+/// round is a request of its own as over HTTP.
+///
+/// each round sends the haves found in common so far along with the next batch of
+/// untried ones, ending with a flush instead of `done`. the server replies with
+/// `ACK <hash> common` for the ones it has, `ACK <hash> ready` once it has enough of
+/// them to send a small pack, and a closing `NAK`. rounds go on until the server is
+/// ready or the haves run out, and the final request then carries the common haves
+/// and `done`, to which the server replies with the pack.
+#[derive(Debug, Default)]
+pub struct Negotiation {
+    untried: VecDeque<String>,
+    common: Vec<String>,
+    ready: bool,
+}
+
+impl Negotiation {
+    /// how many untried haves are sent per round
+    const BATCH: usize = 32;
+
+    pub fn new(haves: Vec<String>) -> Self {
+        Self {
+            untried: haves.into(),
+            ..Default::default()
+        }
+    }
+
+    /// the haves to send in the next round, or `None` once it's time for `done`
+    pub fn next_round(&mut self) -> Option<Vec<String>> {
+        if self.ready || self.untried.is_empty() {
+            return None;
+        }
+        let batch = self.untried.len().min(Self::BATCH);
+        let mut haves = self.common.clone();
+        haves.extend(self.untried.drain(..batch));
+        Some(haves)
+    }
+
+    /// records the server's reply to a round
+    pub fn receive(&mut self, acks: Acknowledgments) {
+        for hex in acks.common {
+            if !self.common.contains(&hex) {
+                self.common.push(hex);
+            }
+        }
+        self.ready |= acks.ready;
+    }
+
+    /// the haves the server has acknowledged, to send along with `done`
+    pub fn common(&self) -> &[String] {
+        &self.common
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct PacketLineBuilder {
     wants: Vec<String>,
+    haves: Vec<String>,
+    capabilities: Capabilities,
+    shallow: Vec<String>,
+    depth: Option<u32>,
+    no_progress: bool,
+    round: bool,
 }
 impl PacketLineBuilder {
     pub fn new() -> Self {
@@ -225,49 +1038,218 @@ impl PacketLineBuilder {
         self.wants.push(hex)
     }
 
-    pub fn build(self) -> PacketLine {
+    /// tells the server the client already has the object `hex`, and so everything
+    /// reachable from it
+    pub fn have(&mut self, hex: String) {
+        self.haves.push(hex)
+    }
+
+    /// capabilities to request, sent along with the first want line
+    pub fn capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities
+    }
+
+    /// tells the server the client has the commit `hex` but not its parents
+    pub fn shallow(&mut self, hex: String) {
+        self.shallow.push(hex)
+    }
+
+    /// limits the fetched history to `depth` commits from the wanted tips
+    pub fn deepen(&mut self, depth: u32) {
+        self.depth = Some(depth)
+    }
+
+    /// asks the server not to send progress messages over side-band
+    pub fn no_progress(&mut self) {
+        self.no_progress = true
+    }
+
+    /// ends the haves with a flush instead of `done`, for a negotiation round the server
+    /// only acknowledges them in
+    pub fn round(&mut self) {
+        self.round = true
+    }
+
+    /// the raw upload-pack request body
+    pub fn build(self) -> Vec<u8> {
         let mut data = Vec::new();
-        for hex in self.wants {
-            let _ = writeln!(data, "{:04x}want {hex}", 4 + 5 + hex.len() + 1);
+        let mut capabilities = self.capabilities.to_string();
+        if self.no_progress {
+            capabilities = format!("{capabilities} no-progress")
+                .trim_start()
+                .to_string();
+        }
+        for (i, hex) in self.wants.into_iter().enumerate() {
+            let line = match i == 0 && !capabilities.is_empty() {
+                true => format!("want {hex} {capabilities}\n"),
+                false => format!("want {hex}\n"),
+            };
+            let _ = write!(data, "{:04x}{line}", 4 + line.len());
+        }
+        for hex in self.shallow {
+            let line = format!("shallow {hex}\n");
+            let _ = write!(data, "{:04x}{line}", 4 + line.len());
+        }
+        if let Some(depth) = self.depth {
+            let line = format!("deepen {depth}\n");
+            let _ = write!(data, "{:04x}{line}", 4 + line.len());
         }
         let _ = write!(data, "0000");
-        let _ = writeln!(data, "0009done");
-        PacketLine { data }
+        for hex in self.haves {
+            let line = format!("have {hex}\n");
+            let _ = write!(data, "{:04x}{line}", 4 + line.len());
+        }
+        match self.round {
+            true => data.extend(b"0000"),
+            false => data.extend(b"0009done\n"),
+        }
+        data
+    }
+
+    /// the raw protocol v2 `fetch` command. capabilities are left out as the ones the
+    /// v0 request negotiates are either implied or not applicable in v2.
+    pub fn build_v2(self) -> Vec<u8> {
+        let mut args: Vec<String> = self.wants.iter().map(|hex| format!("want {hex}")).collect();
+        args.extend(self.haves.iter().map(|hex| format!("have {hex}")));
+        args.extend(self.shallow.iter().map(|hex| format!("shallow {hex}")));
+        if let Some(depth) = self.depth {
+            args.push(format!("deepen {depth}"));
+        }
+        if self.no_progress {
+            args.push("no-progress".into());
+        }
+        args.push("done".into());
+        encode_command("fetch", &args)
     }
 }
 
 impl<'a> TryFrom<&'a [u8]> for PacketLine {
     type Error = anyhow::Error;
 
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        Ok(PacketLineRef::try_from(value)?.into_owned())
+    }
+}
+
+/// a packet line whose data borrows from the stream it was parsed out of, to read
+/// responses without copying every line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketLineRef<'a> {
+    Flush,
+    Delim,
+    ResponseEnd,
+    Data(&'a [u8]),
+}
+
+impl<'a> PacketLineRef<'a> {
+    /// the number of bytes the packet takes up in the stream, length prefix included
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Self::Data(data) => data.len() + 4,
+            _ => 4,
+        }
+    }
+
+    pub fn is_flush(&self) -> bool {
+        matches!(self, Self::Flush)
+    }
+
+    /// the payload of a data packet, `None` for the special ones
+    pub fn data(&self) -> Option<&'a [u8]> {
+        match self {
+            Self::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn into_owned(self) -> PacketLine {
+        match self {
+            Self::Flush => PacketLine::Flush,
+            Self::Delim => PacketLine::Delim,
+            Self::ResponseEnd => PacketLine::ResponseEnd,
+            Self::Data(data) => PacketLine::Data(data.to_vec()),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PacketLineRef<'a> {
+    type Error = anyhow::Error;
+
+    /// parses the packet line `value` starts with
     fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
         if value.len() < 4 {
             anyhow::bail!("packet line size can't be less than four")
         }
         let len: u32 = u32::from_str_radix(str::from_utf8(&value[..4])?, 16)?;
         let len = len as usize;
-        if len + 4 > value.len() {
-            anyhow::bail!("packet line size greater than the byte stream")
-        }
         match len {
-            0 => Ok(Self { data: Vec::new() }),
-            _ => Ok(Self {
-                data: value[4..len].to_vec(),
-            }),
+            0 => Ok(Self::Flush),
+            1 => Ok(Self::Delim),
+            2 => Ok(Self::ResponseEnd),
+            3 => anyhow::bail!("invalid packet line length {len}"),
+            _ if len > value.len() => {
+                anyhow::bail!("packet line size greater than the byte stream")
+            }
+            _ => Ok(Self::Data(&value[4..len])),
+        }
+    }
+}
+
+/// iterates over the packet lines of `stream`, borrowing their data. like
+/// [`PacketLineIterator`], it yields an error for a malformed packet and stops there.
+pub fn packet_lines(stream: &[u8]) -> PacketLineRefs<'_> {
+    PacketLineRefs { stream }
+}
+
+pub struct PacketLineRefs<'a> {
+    stream: &'a [u8],
+}
+
+impl<'a> Iterator for PacketLineRefs<'a> {
+    type Item = anyhow::Result<PacketLineRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stream.is_empty() {
+            return None;
+        }
+        match PacketLineRef::try_from(self.stream) {
+            Ok(next_packet) => {
+                self.stream = &self.stream[next_packet.encoded_len()..];
+                Some(Ok(next_packet))
+            }
+            Err(e) => {
+                self.stream = &[];
+                Some(Err(e))
+            }
         }
     }
 }
 
+/// iterates over the packet lines of a stream, ending once the stream is consumed.
+/// a malformed or truncated packet is yielded as an error, after which the iterator
+/// is exhausted.
 pub struct PacketLineIterator {
-    stream: Vec<u8>,
+    /// what's left of the stream, advanced past each line rather than shifted
+    stream: bytes::Bytes,
 }
 
 impl Iterator for PacketLineIterator {
-    type Item = PacketLine;
+    type Item = anyhow::Result<PacketLine>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_packet = PacketLine::try_from(self.stream.as_slice()).ok()?;
-        self.stream.drain(..next_packet.len() + 4);
-        Some(next_packet)
+        if self.stream.is_empty() {
+            return None;
+        }
+        match PacketLine::try_from(&self.stream[..]) {
+            Ok(next_packet) => {
+                bytes::Buf::advance(&mut self.stream, next_packet.encoded_len());
+                Some(Ok(next_packet))
+            }
+            Err(e) => {
+                self.stream.clear();
+                Some(Err(e))
+            }
+        }
     }
 }
 
@@ -277,9 +1259,7 @@ pub trait IntoPackeLineIterator {
 
 impl IntoPackeLineIterator for bytes::Bytes {
     fn into_packet_line_iter(self) -> PacketLineIterator {
-        PacketLineIterator {
-            stream: self.to_vec(),
-        }
+        PacketLineIterator { stream: self }
     }
 }
 
@@ -291,8 +1271,286 @@ impl TryFrom<ObjectType> for ObjectKind {
             ObjectType::Commit => Self::Commit,
             ObjectType::Tree => Self::Tree,
             ObjectType::Blob => Self::Blob,
-            ObjectType::Tag => Self::Commit,
+            ObjectType::Tag => Self::Tag,
             ObjectType::RefDelta | ObjectType::OfsDelta => anyhow::bail!("not an ObjectKind"),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackIndex;
+
+    /// written by `git repack -adf`, with a blob deltified against a blob that is
+    /// itself an offset delta
+    const OFS_DELTA_PACK: &[u8] = include_bytes!("../tests/fixtures/ofs-delta.pack");
+    const OFS_DELTA_IDX: &[u8] = include_bytes!("../tests/fixtures/ofs-delta.idx");
+
+    #[test]
+    fn resolves_the_offset_deltas_of_a_git_pack() {
+        let packet = Packet::from_bytes(OFS_DELTA_PACK).unwrap();
+        assert_eq!(packet.objects.len(), 9);
+
+        let deltas: Vec<_> = packet
+            .entries
+            .iter()
+            .filter_map(|e| Some((hex::encode(e.hash), hex::encode(e.base?), e.depth)))
+            .collect();
+        assert_eq!(
+            deltas,
+            [
+                (
+                    "a850f0c6532c78e5b987aeda99717fbeb42ef276".to_string(),
+                    "707b3929095a6b8b0e380eb8b975ee46197b1a88".to_string(),
+                    1
+                ),
+                (
+                    "e9f1816de795d8e46914856d53c0f1de4291ce89".to_string(),
+                    "a850f0c6532c78e5b987aeda99717fbeb42ef276".to_string(),
+                    2
+                ),
+            ]
+        );
+
+        // offsets and CRCs match the index git wrote for the pack
+        let ours = PackIndex::from_packet(&packet);
+        let git = PackIndex::try_from(OFS_DELTA_IDX).unwrap();
+        assert_eq!(ours.pack_checksum, git.pack_checksum);
+        let key = |index: &PackIndex| -> Vec<_> {
+            index
+                .entries
+                .iter()
+                .map(|e| (e.hash, e.offset, e.crc32))
+                .collect()
+        };
+        assert_eq!(key(&ours), key(&git));
+    }
+
+    /// a pack entry of `obj_type` whose data inflates to `data`, preceded by `base` for
+    /// deltas
+    fn entry(obj_type: u8, base: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut size = data.len();
+        let mut entry = vec![obj_type << 4 | (size & 0b1111) as u8];
+        size >>= 4;
+        while size > 0 {
+            *entry.last_mut().unwrap() |= 0b1000_0000;
+            entry.push((size & 0b0111_1111) as u8);
+            size >>= 7;
+        }
+        entry.extend(base);
+        let mut deflated = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        deflated.write_all(data).unwrap();
+        entry.extend(deflated.finish().unwrap());
+        entry
+    }
+
+    /// a pack of `entries`, header and trailer included
+    fn pack(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut pack = b"PACK\0\0\0\x02".to_vec();
+        pack.extend((entries.len() as u32).to_be_bytes());
+        for entry in entries {
+            pack.extend(entry);
+        }
+        let checksum: [u8; 20] = sha1::Sha1::digest(&pack).into();
+        pack.extend(checksum);
+        pack
+    }
+
+    /// turns `hello world\n` into `hello there\n`: copies the first six bytes of the
+    /// base and inserts the rest
+    const DELTA: &[u8] = b"\x0c\x0c\x90\x06\x06there\n";
+
+    fn blob(body: &[u8]) -> Object {
+        Object {
+            kind: ObjectKind::Blob,
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn rejects_an_offset_delta_without_a_base_entry() {
+        // a lone offset delta pointing 5 bytes back, before the first entry
+        let pack = pack(&[entry(6, &[5], &[0, 0, 0])]);
+        let err = Packet::from_bytes(&pack).unwrap_err();
+        assert!(err.to_string().contains("has no base"), "{err}");
+    }
+
+    #[test]
+    fn resolves_ref_deltas_that_come_before_their_base() {
+        let base = blob(b"hello world\n");
+        let ordered = pack(&[entry(7, &base.hash(), DELTA), entry(3, &[], &base.body)]);
+        let packet = Packet::from_bytes(&ordered).unwrap();
+        assert_eq!(packet.objects.len(), 2);
+        let target = blob(b"hello there\n");
+        assert_eq!(packet.objects[&target.hash()].body, target.body);
+        let delta = packet.entries.iter().find(|e| e.hash == target.hash());
+        assert_eq!(delta.unwrap().base, Some(base.hash()));
+
+        // without the base in the pack or elsewhere, the delta can't be resolved
+        let thin = pack(&[entry(7, &base.hash(), DELTA)]);
+        let err = Packet::from_bytes(&thin).unwrap_err();
+        assert!(err.to_string().contains("could not be resolved"), "{err}");
+        let packet = Packet::from_bytes_with_bases(&thin, Verbosity::Quiet, |hash| {
+            (*hash == base.hash()).then(|| base.clone())
+        });
+        assert_eq!(packet.unwrap().objects[&target.hash()].body, target.body);
+    }
+
+    #[test]
+    fn does_not_trust_the_object_count_of_the_header() {
+        // claims four billion objects, which would take hundreds of gigabytes to make
+        // room for, but holds none
+        let pack = b"PACK\0\0\0\x02\xff\xff\xff\xff";
+        assert!(Packet::from_bytes(pack).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        // an entry header whose size keeps going for twelve continuation bytes
+        let mut header = vec![0b1011_0000];
+        header.extend([0xff; 11]);
+        header.push(0x7f);
+        let err = read_entry_header(header.as_slice()).err().unwrap();
+        assert!(err.to_string().contains("too large"), "{err}");
+
+        let mut delta = vec![0xff; 11];
+        delta.push(0x7f);
+        let base = Object {
+            kind: ObjectKind::Blob,
+            body: Vec::new(),
+        };
+        let err = apply_delta(&delta, &base).unwrap_err();
+        assert!(err.to_string().contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn rejects_deltas_that_do_not_fit() {
+        let base = blob(b"hello world\n");
+        assert_eq!(apply_delta(DELTA, &base).unwrap().body, b"hello there\n");
+
+        let expect_err = |delta: &[u8], message: &str| {
+            let err = apply_delta(delta, &base).unwrap_err();
+            assert!(err.to_string().contains(message), "{err}");
+        };
+        // copies 8 bytes from offset 6 of the 12 byte base
+        expect_err(b"\x0c\x0e\x91\x06\x08", "out of a 12 bytes base");
+        // inserts 6 bytes with only 5 left
+        expect_err(b"\x0c\x0c\x90\x06\x06ther", "past its end");
+        // promises 13 bytes but produces 12
+        expect_err(
+            b"\x0c\x0d\x90\x06\x06there\n",
+            "produced 12 bytes, expected 13",
+        );
+        // meant for a different base
+        expect_err(b"\x0b\x0c\x90\x06\x06there\n", "expects a 11 bytes base");
+        expect_err(b"\x0c\x0c\x00", "reserved delta instruction");
+    }
+
+    #[test]
+    fn unpacks_a_large_pack() {
+        let base = blob(b"hello world\n");
+        // a blob larger than any buffer the unpacker starts out with
+        let large = blob(&(0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>());
+        let mut entries = vec![entry(3, &[], &base.body), entry(3, &[], &large.body)];
+        let mut expected = vec![base.clone(), large];
+        for i in 0..2_000 {
+            let small = blob(format!("blob {i}\n").as_bytes());
+            entries.push(entry(3, &[], &small.body));
+            expected.push(small);
+        }
+        // deltas that insert all of their target, against the first blob
+        for i in 0..500 {
+            let target = format!("delta {i}\n");
+            let mut delta = vec![
+                base.body.len() as u8,
+                target.len() as u8,
+                target.len() as u8,
+            ];
+            delta.extend(target.as_bytes());
+            entries.push(entry(7, &base.hash(), &delta));
+            expected.push(blob(target.as_bytes()));
+        }
+
+        let packet = Packet::from_bytes(&pack(&entries)).unwrap();
+        assert_eq!(packet.entries.len(), expected.len());
+        assert_eq!(packet.objects.len(), expected.len());
+        for (entry, object) in packet.entries.iter().zip(&expected) {
+            assert_eq!(entry.hash, object.hash());
+            assert_eq!(packet.objects[&entry.hash].body, object.body);
+        }
+    }
+
+    /// the packet lines of `lines`, as a server sends them
+    fn packets(lines: &[String]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for line in lines {
+            PacketLine::Data(format!("{line}\n").into_bytes()).encode(&mut stream);
+        }
+        stream
+    }
+
+    fn hashes(n: usize) -> Vec<String> {
+        (1..=n).map(|i| format!("{i:040x}")).collect()
+    }
+
+    #[test]
+    fn parses_multi_ack_detailed_replies() {
+        let [a, b, c] = <[String; 3]>::try_from(hashes(3)).unwrap();
+
+        let (acks, len) = Acknowledgments::parse(&packets(&["NAK".into()])).unwrap();
+        assert!(acks.common.is_empty() && !acks.ready);
+        assert_eq!(len, 8);
+
+        // a round the server found common objects in, and enough of them for a pack.
+        // `ready` may name an object the server doesn't have.
+        let round = packets(&[
+            format!("ACK {a} common"),
+            format!("ACK {b} common"),
+            format!("ACK {a} common"),
+            format!("ACK {c} ready"),
+            "NAK".into(),
+        ]);
+        let mut stream = round.clone();
+        stream.extend(b"PACK\0\0\0\x02");
+        let (acks, len) = Acknowledgments::parse(&stream).unwrap();
+        assert_eq!(acks.common, [a.clone(), b.clone()]);
+        assert!(acks.ready);
+        assert_eq!(len, round.len());
+
+        // the reply to `done`, a plain ack of the last common object
+        let (acks, _) = Acknowledgments::parse(&packets(&[format!("ACK {b}")])).unwrap();
+        assert_eq!(acks.common, [b]);
+        assert!(!acks.ready);
+    }
+
+    #[test]
+    fn negotiates_in_rounds_until_the_server_is_ready() {
+        let haves = hashes(40);
+        let mut negotiation = Negotiation::new(haves.clone());
+        assert_eq!(negotiation.next_round().unwrap(), haves[..32]);
+        negotiation.receive(Acknowledgments {
+            common: vec![haves[2].clone()],
+            ready: false,
+        });
+        // the common haves are sent again along with the rest
+        let round = negotiation.next_round().unwrap();
+        assert_eq!(round[0], haves[2]);
+        assert_eq!(round[1..], haves[32..]);
+        negotiation.receive(Acknowledgments::default());
+        assert_eq!(negotiation.next_round(), None);
+        assert_eq!(negotiation.common(), [haves[2].clone()]);
+
+        let mut negotiation = Negotiation::new(haves.clone());
+        negotiation.next_round().unwrap();
+        negotiation.receive(Acknowledgments {
+            common: vec![haves[0].clone()],
+            ready: true,
+        });
+        // haves are left, but the server has enough
+        assert_eq!(negotiation.next_round(), None);
+        assert_eq!(negotiation.common(), [haves[0].clone()]);
+
+        assert_eq!(Negotiation::new(Vec::new()).next_round(), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{fs::Metadata, io, path::Path};
+
+/// the mode git records for a file with the given metadata: `40000` for directories,
+/// `120000` for symlinks and `100755` or `100644` depending on whether it's executable
+pub fn mode_of(metadata: &Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o40000
+    } else if metadata.is_symlink() {
+        0o120000
+    } else if is_executable(metadata) {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // git only records whether the owner may execute the file
+    metadata.mode() & 0o100 != 0
+}
+
+#[cfg(windows)]
+fn is_executable(_metadata: &Metadata) -> bool {
+    // there is no execute bit to go by, so files are never assumed executable
+    false
+}
+
+/// applies the permission bits of the git `mode` to the file at `path`
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))
+}
+
+/// permissions can't be expressed as a mode here, so files keep their defaults
+#[cfg(windows)]
+pub fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// creates a symlink at `link` pointing at `target`, the raw content of a symlink blob
+#[cfg(unix)]
+pub fn symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), link)
+}
+
+/// symlinks need extra privileges here, so like git without `core.symlinks` the link
+/// is checked out as a plain file holding its target
+#[cfg(windows)]
+pub fn symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    std::fs::write(link, target)
+}
+
+/// the space the file takes up on disk, which for small files is usually more than its
+/// length
+#[cfg(unix)]
+pub fn disk_usage(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512
+}
+
+#[cfg(windows)]
+pub fn disk_usage(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// the stat data the index caches to tell whether a file changed
+pub(crate) struct Stat {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+#[cfg(unix)]
+pub(crate) fn stat_of(metadata: &Metadata) -> Stat {
+    use std::os::unix::fs::MetadataExt;
+    Stat {
+        ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+        mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+        dev: metadata.dev() as u32,
+        ino: metadata.ino() as u32,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        size: metadata.size() as u32,
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn stat_of(metadata: &Metadata) -> Stat {
+    let since_epoch = |time: io::Result<std::time::SystemTime>| {
+        time.ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or((0, 0), |d| (d.as_secs() as u32, d.subsec_nanos()))
+    };
+    Stat {
+        ctime: since_epoch(metadata.created()),
+        mtime: since_epoch(metadata.modified()),
+        dev: 0,
+        ino: 0,
+        uid: 0,
+        gid: 0,
+        size: metadata.len() as u32,
+    }
+}
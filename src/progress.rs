@@ -0,0 +1,86 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// how much a long running operation reports on stderr
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// nothing at all
+    Quiet,
+    /// a single progress line, updated in place
+    #[default]
+    Normal,
+    /// a line for every processed item
+    Verbose,
+}
+
+/// minimum time between two redraws of the progress line
+const THROTTLE: Duration = Duration::from_millis(100);
+
+/// counts the items an operation went through, e.g. the objects of a pack, reporting
+/// them according to its verbosity
+pub struct Progress {
+    title: String,
+    total: Option<usize>,
+    done: usize,
+    verbosity: Verbosity,
+    last_draw: Option<Instant>,
+}
+
+impl Progress {
+    /// `total` is the number of items expected, if known upfront
+    pub fn new(title: impl Into<String>, total: Option<usize>, verbosity: Verbosity) -> Self {
+        Self {
+            title: title.into(),
+            total,
+            done: 0,
+            verbosity,
+            last_draw: None,
+        }
+    }
+
+    pub fn quiet() -> Self {
+        Self::new("", None, Verbosity::Quiet)
+    }
+
+    /// counts one more item. `detail` describes it and is only built when verbose.
+    pub fn tick<D: Display>(&mut self, detail: impl FnOnce() -> D) {
+        self.done += 1;
+        match self.verbosity {
+            Verbosity::Quiet => (),
+            Verbosity::Verbose => eprintln!("{}", detail()),
+            Verbosity::Normal => {
+                if self.last_draw.is_some_and(|t| t.elapsed() < THROTTLE) {
+                    return;
+                }
+                self.last_draw = Some(Instant::now());
+                eprint!("\r{}", self.line());
+            }
+        }
+    }
+
+    /// prints `message` when verbose, without counting it as an item
+    pub fn log<D: Display>(&self, message: impl FnOnce() -> D) {
+        if self.verbosity == Verbosity::Verbose {
+            eprintln!("{}", message());
+        }
+    }
+
+    /// prints the final count once all items are processed
+    pub fn finish(&self) {
+        if self.verbosity == Verbosity::Normal && self.done > 0 {
+            eprintln!("\r{}, done.", self.line());
+        }
+    }
+
+    fn line(&self) -> String {
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{}: {:3}% ({}/{total})",
+                self.title,
+                self.done * 100 / total,
+                self.done
+            ),
+            _ => format!("{}: {}", self.title, self.done),
+        }
+    }
+}
@@ -0,0 +1,623 @@
+use anyhow::Context;
+use std::{io::Write, path::Path, str};
+
+use crate::{Capabilities, PacketLine};
+
+/// the all-zero object id, advertised in place of a ref by empty repositories
+pub const ZERO_ID: &str = "0000000000000000000000000000000000000000";
+
+/// a ref as advertised by a remote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ref {
+    pub name: String,
+    pub hash: String,
+    /// for annotated tags, the object the tag points at
+    pub peeled: Option<String>,
+    /// for symbolic refs like HEAD, the ref they point at. only reported over protocol v2.
+    pub symref_target: Option<String>,
+}
+
+/// parses the ref lines of an `info/refs` advertisement, along with the capabilities
+/// the first of them carries after a `\0`. the `^{}` lines of peeled tags are folded
+/// into the tag they follow.
+pub fn parse_advertisement<I>(lines: I) -> anyhow::Result<(Vec<Ref>, Capabilities)>
+where
+    I: IntoIterator<Item = PacketLine>,
+{
+    let mut refs: Vec<Ref> = Vec::new();
+    let mut capabilities = Capabilities::default();
+    for (i, packet_line) in lines.into_iter().enumerate() {
+        let data = packet_line.data().ok_or(anyhow::anyhow!(
+            "unexpected special packet in ref advertisement"
+        ))?;
+        let pos = data
+            .iter()
+            .position(|c| *c == b'\0' || *c == b'\n')
+            .unwrap_or(data.len());
+        if i == 0 && data.get(pos) == Some(&b'\0') {
+            let caps = str::from_utf8(&data[pos + 1..])?;
+            capabilities = Capabilities::parse(caps);
+        }
+        let line = str::from_utf8(&data[..pos])?;
+        let (hash, name) = line
+            .split_once(' ')
+            .filter(|(hash, name)| is_hex_hash(hash) && !name.is_empty())
+            .ok_or(anyhow::anyhow!("malformed ref advertisement [{line}]"))?;
+        // an empty repository only advertises its capabilities
+        if hash == ZERO_ID {
+            continue;
+        }
+
+        if let Some(tag) = name.strip_suffix("^{}") {
+            match refs.iter_mut().rev().find(|r| r.name == tag) {
+                Some(r) => r.peeled = Some(hash.into()),
+                None => anyhow::bail!("peeled ref {name} does not follow its tag"),
+            }
+            continue;
+        }
+        refs.push(Ref {
+            name: name.into(),
+            hash: hash.into(),
+            peeled: None,
+            symref_target: None,
+        });
+    }
+    Ok((refs, capabilities))
+}
+
+/// parses the response to a protocol v2 `ls-refs` command. each line holds a hash and a
+/// ref name, optionally followed by `symref-target:` and `peeled:` attributes.
+pub fn parse_ls_refs<I>(lines: I) -> anyhow::Result<Vec<Ref>>
+where
+    I: IntoIterator<Item = PacketLine>,
+{
+    let mut refs = Vec::new();
+    for packet_line in lines.into_iter().take_while(|p| !p.is_flush()) {
+        let data = packet_line.data().ok_or(anyhow::anyhow!(
+            "unexpected special packet in ls-refs response"
+        ))?;
+        let line = str::from_utf8(data)?.trim_end();
+        let mut fields = line.split(' ');
+        let (Some(hash), Some(name)) = (fields.next(), fields.next()) else {
+            anyhow::bail!("malformed ls-refs line [{line}]");
+        };
+        anyhow::ensure!(is_hex_hash(hash), "malformed ls-refs line [{line}]");
+        let mut r = Ref {
+            name: name.into(),
+            hash: hash.into(),
+            peeled: None,
+            symref_target: None,
+        };
+        for attribute in fields {
+            if let Some(target) = attribute.strip_prefix("symref-target:") {
+                r.symref_target = Some(target.into());
+            } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                r.peeled = Some(peeled.into());
+            }
+        }
+        refs.push(r);
+    }
+    Ok(refs)
+}
+
+/// rejects names that are empty or could escape the git directory
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.split('/').any(|part| part == ".." || part.is_empty())
+}
+
+/// whether `name` is one of the refs git keeps at the top of the git directory, which
+/// are all caps like `HEAD` or `FETCH_HEAD`
+fn is_pseudo_ref(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|c| c.is_ascii_uppercase() || c == b'_')
+}
+
+/// whether `name` is a file of the git directory that holds a ref, as opposed to the
+/// other files there such as `config` or `index`, which must not be read or written as
+/// refs
+fn is_ref_path(name: &str) -> bool {
+    is_valid_name(name) && (name.starts_with("refs/") || is_pseudo_ref(name))
+}
+
+fn is_hex_hash(hash: &str) -> bool {
+    hash.len() == 40 && hash.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+/// writes `hex` into the ref file `name` (e.g. `refs/heads/master`) inside `git_dir`
+pub fn write_ref(git_dir: &Path, name: &str, hex: &str) -> anyhow::Result<()> {
+    if !is_hex_hash(hex) {
+        anyhow::bail!("{hex} is not a valid object name");
+    }
+    if !is_ref_path(name) {
+        anyhow::bail!("'{name}' is not a valid ref name");
+    }
+    let path = git_dir.join(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .context(format!("failed to create the directory for ref {name}"))?;
+    }
+    std::fs::write(&path, format!("{hex}\n")).context(format!("failed to write ref {name}"))
+}
+
+/// makes `name` (e.g. `HEAD`) a symbolic ref pointing at `target`
+pub fn write_symbolic_ref(git_dir: &Path, name: &str, target: &str) -> anyhow::Result<()> {
+    if !is_ref_path(name) {
+        anyhow::bail!("'{name}' is not a valid ref name");
+    }
+    if !is_ref_path(target) || target.ends_with(['/', '.']) || target.contains("..") {
+        anyhow::bail!("'{target}' is not a valid ref name");
+    }
+    if name == "HEAD" && !target.starts_with("refs/") {
+        anyhow::bail!("refusing to point HEAD outside of refs/");
+    }
+    let path = git_dir.join(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .context(format!("failed to create the directory for ref {name}"))?;
+    }
+    std::fs::write(&path, format!("ref: {target}\n"))
+        .context(format!("failed to write symbolic ref {name}"))
+}
+
+/// points `name` at `hex`, recording the move with `message` in the reflog. if `name`
+/// is a symbolic ref, such as `HEAD` on a branch, the ref it points at is updated
+/// instead and the move is logged for both.
+pub fn update_ref(git_dir: &Path, name: &str, hex: &str, message: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(is_ref_path(name), "'{name}' is not a valid ref name");
+    let mut target = name.to_owned();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        if git_dir.join(&target).is_file() {
+            if let Some(next) = read_symbolic_ref(git_dir, &target)? {
+                target = next;
+                continue;
+            }
+        }
+        let old = read_ref(git_dir, &target).ok();
+        write_ref(git_dir, &target, hex)?;
+        append_reflog(git_dir, &target, old.as_deref(), hex, message);
+        if target != name {
+            append_reflog(git_dir, name, old.as_deref(), hex, message);
+        }
+        return Ok(());
+    }
+    anyhow::bail!("too many levels of symbolic refs for {name}")
+}
+
+/// appends the move of `name` from `old` to `new` to its reflog at `logs/<name>`, as
+/// an `<old> <new> <committer>\t<message>` line. a ref that didn't exist moves from
+/// the zero id.
+///
+/// like git, HEAD, branches and remote-tracking refs are logged unless
+/// `core.logAllRefUpdates` says otherwise, which it does by default in bare
+/// repositories, and refs that already have a reflog always are. the reflog only
+/// helps recovering lost commits, so failing to write it is merely warned about.
+pub fn append_reflog(git_dir: &Path, name: &str, old: Option<&str>, new: &str, message: &str) {
+    if let Err(e) = try_append_reflog(git_dir, name, old, new, message) {
+        eprintln!("warning: failed to update the reflog of {name}: {e:#}");
+    }
+}
+
+fn try_append_reflog(
+    git_dir: &Path,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let path = git_dir.join("logs").join(name);
+    let config = crate::Config::load(git_dir)?;
+    if !path.is_file() {
+        let enabled = match config.get("core.logallrefupdates") {
+            Some(value) => !matches!(
+                value.to_ascii_lowercase().as_str(),
+                "false" | "no" | "off" | "0"
+            ),
+            None => config.get("core.bare") != Some("true"),
+        };
+        let logged = name == "HEAD"
+            || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix));
+        if !enabled || !logged {
+            return Ok(());
+        }
+    }
+    let committer = crate::Signature::committer(&config)?;
+    let mut entry = format!("{} {new} {committer}", old.unwrap_or(ZERO_ID));
+    if !message.is_empty() {
+        entry.push('\t');
+        entry.push_str(&message.replace('\n', " "));
+    }
+    entry.push('\n');
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("failed to create the reflog directory")?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(entry.as_bytes()))
+        .context(format!("failed to write {path:?}"))
+}
+
+/// lists every ref under `refs/` of `git_dir` along with the hash it resolves to,
+/// sorted by name
+pub fn list_refs(git_dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    fn walk(git_dir: &Path, name: &str, refs: &mut Vec<(String, String)>) -> anyhow::Result<()> {
+        let dir = git_dir.join(name);
+        for entry in std::fs::read_dir(&dir).context(format!("failed to read {dir:?}"))? {
+            let entry = entry?;
+            let name = format!("{name}/{}", entry.file_name().to_string_lossy());
+            match entry.file_type()?.is_dir() {
+                true => walk(git_dir, &name, refs)?,
+                false => {
+                    let hash = read_ref(git_dir, &name)?;
+                    refs.push((name, hash));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut refs = Vec::new();
+    if git_dir.join("refs").is_dir() {
+        walk(git_dir, "refs", &mut refs)?;
+    }
+    // loose refs take precedence over their packed counterpart
+    for packed in read_packed_refs(git_dir)? {
+        if !refs.iter().any(|(name, _)| *name == packed.name) {
+            refs.push((packed.name, packed.hash));
+        }
+    }
+    refs.sort();
+    Ok(refs)
+}
+
+/// parses `packed-refs` of `git_dir`, a missing file holds no refs.
+/// `^<hash>` lines record the peeled target of the tag right above them.
+pub fn read_packed_refs(git_dir: &Path) -> anyhow::Result<Vec<Ref>> {
+    let path = git_dir.join("packed-refs");
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(&path).context("failed to read the packed-refs file")?;
+
+    let mut refs: Vec<Ref> = Vec::new();
+    for line in content.lines() {
+        // the header lists the traits of the file, e.g. `# pack-refs with: peeled`
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            match refs.last_mut() {
+                Some(r) if is_hex_hash(peeled) => r.peeled = Some(peeled.into()),
+                _ => anyhow::bail!("malformed packed-refs line [{line}]"),
+            }
+            continue;
+        }
+        let (hash, name) = line
+            .split_once(' ')
+            .filter(|(hash, name)| is_hex_hash(hash) && is_valid_name(name))
+            .ok_or(anyhow::anyhow!("malformed packed-refs line [{line}]"))?;
+        refs.push(Ref {
+            name: name.into(),
+            hash: hash.into(),
+            peeled: None,
+            symref_target: None,
+        });
+    }
+    Ok(refs)
+}
+
+/// checks whether the ref `name` exists, either loose or packed
+pub fn ref_exists(git_dir: &Path, name: &str) -> anyhow::Result<bool> {
+    if git_dir.join(name).is_file() {
+        return Ok(true);
+    }
+    Ok(read_packed_refs(git_dir)?.iter().any(|r| r.name == name))
+}
+
+/// symbolic refs are followed at most this many times, like git does
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// reads the ref `name` inside `git_dir`, following symbolic refs, and returns the
+/// hex hash it ultimately points at
+pub fn read_ref(git_dir: &Path, name: &str) -> anyhow::Result<String> {
+    let mut name = name.to_owned();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        if !git_dir.join(&name).is_file() {
+            // packed refs are never symbolic
+            return read_packed_refs(git_dir)?
+                .into_iter()
+                .find(|r| r.name == name)
+                .map(|r| r.hash)
+                .ok_or(anyhow::anyhow!("failed to read ref {name}"));
+        }
+        let content = std::fs::read_to_string(git_dir.join(&name))
+            .context(format!("failed to read ref {name}"))?;
+        match content.trim_end().strip_prefix("ref: ") {
+            Some(target) => name = target.trim().to_owned(),
+            None => {
+                let hex = content.trim_end();
+                anyhow::ensure!(is_hex_hash(hex), "ref {name} holds an invalid object name");
+                return Ok(hex.to_ascii_lowercase());
+            }
+        }
+    }
+    anyhow::bail!("too many levels of symbolic refs for {name}")
+}
+
+/// returns the target of the symbolic ref `name`, or `None` if it holds a hash
+pub fn read_symbolic_ref(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    let content = std::fs::read_to_string(git_dir.join(name))
+        .context(format!("failed to read ref {name}"))?;
+    Ok(content
+        .trim_end()
+        .strip_prefix("ref: ")
+        .map(|target| target.trim().to_owned()))
+}
+
+/// the places a short ref name is looked up in, in order, like git's rev-parse
+const REF_RULES: &[&str] = &[
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+/// resolves `name` to the hex hash of an object. `name` may be a full hash, a ref such
+/// as `HEAD` or `refs/heads/master`, a short branch/tag name, or an abbreviated hash,
+/// optionally followed by the ancestry suffixes `resolve_rev` understands.
+pub fn resolve(git_dir: &Path, name: &str) -> anyhow::Result<String> {
+    // ref names can't hold either character
+    if name.contains(['~', '^']) {
+        return resolve_rev(git_dir, name);
+    }
+    if is_hex_hash(name) {
+        return Ok(name.to_ascii_lowercase());
+    }
+    if is_valid_name(name) {
+        for rule in REF_RULES {
+            // only refs and all-caps names like `HEAD` are taken as they are
+            if *rule == "{}" && !is_ref_path(name) {
+                continue;
+            }
+            let refname = rule.replace("{}", name);
+            if ref_exists(git_dir, &refname)? {
+                return read_ref(git_dir, &refname);
+            }
+        }
+    }
+    if name.len() >= 4 && name.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(crate::Object::expand_prefix_in(git_dir, name)?);
+    }
+    anyhow::bail!("ambiguous argument '{name}': unknown revision or path")
+}
+
+/// resolves a revision such as `HEAD~3` or `master^2`: a name `resolve` understands,
+/// followed by any number of `~<n>` suffixes, which go `n` first parents up, and
+/// `^<n>` suffixes, which pick the `n`th parent. `n` defaults to 1, and `^0` is the
+/// commit itself. tags along the way are peeled to the commit they point at.
+pub fn resolve_rev(git_dir: &Path, spec: &str) -> anyhow::Result<String> {
+    let start = spec.find(['~', '^']).unwrap_or(spec.len());
+    let (base, mut suffixes) = spec.split_at(start);
+    let mut hash = resolve(git_dir, base)?;
+    while let Some(op) = suffixes.chars().next() {
+        anyhow::ensure!(
+            op == '~' || op == '^',
+            "invalid revision suffix in '{spec}'"
+        );
+        let digits = suffixes[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(suffixes.len(), |end| end + 1);
+        let n = match &suffixes[1..digits] {
+            "" => 1,
+            n => n
+                .parse::<usize>()
+                .context(format!("invalid revision suffix in '{spec}'"))?,
+        };
+        suffixes = &suffixes[digits..];
+        match (op, n) {
+            (_, 0) => hash = peel_to_commit(git_dir, &hash)?.0,
+            ('~', _) => {
+                for _ in 0..n {
+                    let (current, commit) = peel_to_commit(git_dir, &hash)?;
+                    hash = commit.parents.into_iter().next().ok_or(anyhow::anyhow!(
+                        "'{spec}' goes past the root commit {current}"
+                    ))?;
+                }
+            }
+            _ => {
+                let (current, commit) = peel_to_commit(git_dir, &hash)?;
+                hash = commit
+                    .parents
+                    .into_iter()
+                    .nth(n - 1)
+                    .ok_or(anyhow::anyhow!(
+                        "'{spec}': commit {current} has no parent {n}"
+                    ))?;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// follows annotated tags from `hash` down to a commit, through as many tags as point
+/// at each other, returning the commit with its hash
+pub fn peel_to_commit(git_dir: &Path, hash: &str) -> anyhow::Result<(String, crate::Commit)> {
+    let mut hash = hash.to_owned();
+    loop {
+        let obj = crate::Object::load_in(git_dir, &hash)?;
+        match obj.kind() {
+            crate::ObjectKind::Commit => return Ok((hash, crate::Commit::try_from(obj)?)),
+            crate::ObjectKind::Tag => hash = crate::Tag::try_from(obj)?.object,
+            kind => anyhow::bail!("{hash} is a {kind}, not a commit"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the `info/refs` a git 2.39 http backend serves, service announcement included
+    const INFO_REFS: &[u8] = include_bytes!("../tests/fixtures/info-refs");
+
+    fn lines(raw: &[u8]) -> Vec<PacketLine> {
+        crate::packet_lines(raw)
+            .map(|line| line.unwrap().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn parses_an_info_refs_advertisement() {
+        let refs = lines(INFO_REFS)
+            .into_iter()
+            .skip_while(|p| !p.is_flush())
+            .skip(1)
+            .take_while(|p| !p.is_flush());
+        let (refs, capabilities) = parse_advertisement(refs).unwrap();
+        assert!(capabilities.contains("multi_ack_detailed"));
+        assert_eq!(capabilities.get("symref"), Some("HEAD:refs/heads/master"));
+
+        let names: Vec<_> = refs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "HEAD",
+                "refs/heads/dev",
+                "refs/heads/master",
+                "refs/tags/v1",
+                "refs/tags/v1a",
+                "refs/tags/v9"
+            ]
+        );
+        assert_eq!(refs[0].hash, "68293b5ced61aef70c00dd81e0efd02dc5c9126d");
+        // lightweight tags have nothing to peel, annotated ones carry their target
+        assert_eq!(refs[3].peeled, None);
+        assert_eq!(
+            refs[4].peeled.as_deref(),
+            Some("4c5344bd6aafbf7d7118ff3aada6ef5916fc81de")
+        );
+        assert_eq!(refs[5].hash, "ff607ff2af50390502cad3e53508193efa6a0ea8");
+        assert_eq!(refs[5].peeled.as_deref(), Some(refs[0].hash.as_str()));
+    }
+
+    #[test]
+    fn rejects_malformed_ref_lines() {
+        let advertise =
+            |line: &str| parse_advertisement([PacketLine::Data(line.as_bytes().to_vec())]);
+        assert!(advertise("short refs/heads/x\n").is_err());
+        assert!(advertise("68293b5ced61aef70c00dd81e0efd02dc5c9126d\n").is_err());
+        assert!(advertise("68293b5ced61aef70c00dd81e0efd02dc5c9126d refs/tags/x^{}\n").is_err());
+        let (refs, _) = advertise(&format!("{ZERO_ID} capabilities^{{}}\0ofs-delta\n")).unwrap();
+        assert!(refs.is_empty());
+    }
+
+    /// writes a commit with `parents` into `git_dir`, returning its hash
+    fn commit(git_dir: &Path, message: &str, parents: &[&str]) -> String {
+        let signature = "A <a@example.com> 1112911993 +0200";
+        let mut body = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n".to_owned();
+        for parent in parents {
+            body.push_str(&format!("parent {parent}\n"));
+        }
+        body.push_str(&format!(
+            "author {signature}\ncommitter {signature}\n\n{message}\n"
+        ));
+        let object = crate::Object::new(crate::ObjectKind::Commit, body.into_bytes());
+        hex::encode(object.persist_in(git_dir).unwrap())
+    }
+
+    #[test]
+    fn resolves_ancestry_suffixes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path();
+        // a - b - c - merge on master, with d branching off a and merged back in
+        let a = commit(git_dir, "a", &[]);
+        let b = commit(git_dir, "b", &[&a]);
+        let c = commit(git_dir, "c", &[&b]);
+        let d = commit(git_dir, "d", &[&a]);
+        let merge = commit(git_dir, "merge", &[&c, &d]);
+        write_ref(git_dir, "refs/heads/master", &merge).unwrap();
+        write_symbolic_ref(git_dir, "HEAD", "refs/heads/master").unwrap();
+        let tag = crate::Tag {
+            object: merge.clone(),
+            kind: crate::ObjectKind::Commit,
+            tag: "v1".into(),
+            tagger: None,
+            message: "v1\n".into(),
+        };
+        let tag = hex::encode(tag.to_object().persist_in(git_dir).unwrap());
+        write_ref(git_dir, "refs/tags/v1", &tag).unwrap();
+
+        let rev = |spec: &str| resolve(git_dir, spec).unwrap();
+        assert_eq!(rev("HEAD"), merge);
+        assert_eq!(rev("HEAD~0"), merge);
+        assert_eq!(rev("HEAD^"), c);
+        assert_eq!(rev("HEAD~1"), c);
+        assert_eq!(rev("master^1"), c);
+        assert_eq!(rev("master^2"), d);
+        assert_eq!(rev("master~2"), b);
+        assert_eq!(rev("master~~~"), a);
+        assert_eq!(rev("master~3"), a);
+        assert_eq!(rev("master^2~1"), a);
+        // a tag resolves to itself, and suffixes peel it to the commit it points at
+        assert_eq!(rev("v1"), tag);
+        assert_eq!(rev("v1^0"), merge);
+        assert_eq!(rev("v1~1"), c);
+        assert_eq!(rev(&format!("{}~2", &merge[..8])), b);
+
+        let err = |spec: &str| resolve(git_dir, spec).unwrap_err().to_string();
+        assert!(err("master~4").contains("past the root commit"));
+        assert!(err("master^3").contains("has no parent 3"));
+        assert!(err("master^^2").contains("has no parent 2"));
+        assert!(err("master^x").contains("invalid revision suffix"));
+    }
+
+    #[test]
+    fn only_writes_refs_and_pseudo_refs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path();
+        std::fs::write(git_dir.join("config"), "[core]\n").unwrap();
+        let hash = "94954abda49de8615a048f8d2e64b5de848e27a1";
+        for name in [
+            "config",
+            "index",
+            "objects/info/alternates",
+            "Head",
+            "refs/../config",
+        ] {
+            assert!(write_ref(git_dir, name, hash).is_err(), "{name}");
+            assert!(update_ref(git_dir, name, hash, "").is_err(), "{name}");
+            assert!(write_symbolic_ref(git_dir, name, "refs/heads/master").is_err());
+            assert!(write_symbolic_ref(git_dir, "refs/heads/x", name).is_err());
+        }
+        assert_eq!(
+            std::fs::read_to_string(git_dir.join("config")).unwrap(),
+            "[core]\n"
+        );
+
+        write_ref(git_dir, "refs/heads/master", hash).unwrap();
+        update_ref(git_dir, "ORIG_HEAD", hash, "").unwrap();
+        write_symbolic_ref(git_dir, "HEAD", "refs/heads/master").unwrap();
+        assert_eq!(read_ref(git_dir, "HEAD").unwrap(), hash);
+        assert_eq!(read_ref(git_dir, "ORIG_HEAD").unwrap(), hash);
+    }
+
+    #[test]
+    fn resolves_branches_named_like_git_dir_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git_dir = tmp.path();
+        std::fs::write(git_dir.join("config"), "[core]\n").unwrap();
+        std::fs::write(git_dir.join("description"), "unnamed\n").unwrap();
+        let hash = "94954abda49de8615a048f8d2e64b5de848e27a1";
+        for name in ["config", "description", "index"] {
+            write_ref(git_dir, &format!("refs/heads/{name}"), hash).unwrap();
+            assert_eq!(resolve(git_dir, name).unwrap(), hash);
+        }
+        write_ref(git_dir, "FETCH_HEAD", hash).unwrap();
+        assert_eq!(resolve(git_dir, "FETCH_HEAD").unwrap(), hash);
+        assert_eq!(resolve(git_dir, "refs/heads/index").unwrap(), hash);
+    }
+}
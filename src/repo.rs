@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Context};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// the git directory commands operate on: `$GIT_DIR` if set, otherwise the git
+/// directory of the repository enclosing the current directory. outside of any
+/// repository this is `.git`, so that lookups fail with a missing object or ref.
+pub fn git_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("GIT_DIR") {
+        return dir.into();
+    }
+    match find_repo_root() {
+        Ok(root) if is_bare(&root) => root,
+        Ok(root) => root.join(".git"),
+        Err(_) => PathBuf::from(".git"),
+    }
+}
+
+/// walks up from the current directory to the root of the enclosing repository: the
+/// directory holding `.git`, or the git directory itself for a bare repository
+pub fn find_repo_root() -> anyhow::Result<PathBuf> {
+    let cwd = std::env::current_dir().context("failed to read the current directory")?;
+    cwd.ancestors()
+        .find(|dir| dir.join(".git").is_dir() || is_bare(dir))
+        .map(Path::to_path_buf)
+        .ok_or(anyhow!(
+            "not a git repository (or any of the parent directories): .git"
+        ))
+}
+
+/// turns `path`, relative to the current directory, into a path relative to `root`
+pub fn relative_to_root(root: &Path, path: &Path) -> anyhow::Result<PathBuf> {
+    let cwd = std::env::current_dir().context("failed to read the current directory")?;
+    let mut absolute = PathBuf::new();
+    for component in cwd.join(path).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            component => absolute.push(component),
+        }
+    }
+    absolute
+        .strip_prefix(root)
+        .map(Path::to_path_buf)
+        .map_err(|_| anyhow!("{path:?} is outside repository at {root:?}"))
+}
+
+/// the commits of `git_dir` whose parents are missing, as listed in its `shallow` file
+/// by a shallow clone. empty if the history is complete.
+pub fn shallow_commits(git_dir: &Path) -> anyhow::Result<HashSet<String>> {
+    match std::fs::read_to_string(git_dir.join("shallow")) {
+        Ok(content) => Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e).context("failed to read the shallow file"),
+    }
+}
+
+/// records `commits` as the shallow boundary of `git_dir`, removing the `shallow` file
+/// once the history is complete
+pub fn write_shallow_commits(git_dir: &Path, commits: &HashSet<String>) -> anyhow::Result<()> {
+    let path = git_dir.join("shallow");
+    if commits.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).context("failed to remove the shallow file")
+            }
+            _ => Ok(()),
+        };
+    }
+    let mut commits: Vec<&String> = commits.iter().collect();
+    commits.sort();
+    let content: String = commits.iter().map(|hex| format!("{hex}\n")).collect();
+    std::fs::write(path, content).context("failed to write the shallow file")
+}
+
+/// a bare repository keeps HEAD, objects and refs at its top level
+fn is_bare(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
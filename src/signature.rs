@@ -0,0 +1,109 @@
+use anyhow::Context;
+
+use crate::Config;
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// identity and timestamp as recorded in the author/committer lines of a commit
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// seconds since the unix epoch
+    pub time: u64,
+    /// offset from UTC in minutes
+    pub offset: i32,
+}
+
+impl Signature {
+    /// builds the author signature from `GIT_AUTHOR_NAME`/`GIT_AUTHOR_EMAIL`, or else
+    /// `user.name`/`user.email` of `config`
+    pub fn author(config: &Config) -> anyhow::Result<Self> {
+        Self::from_env("AUTHOR", config)
+    }
+
+    /// builds the committer signature from `GIT_COMMITTER_NAME`/`GIT_COMMITTER_EMAIL`,
+    /// or else `user.name`/`user.email` of `config`
+    pub fn committer(config: &Config) -> anyhow::Result<Self> {
+        Self::from_env("COMMITTER", config)
+    }
+
+    fn from_env(role: &str, config: &Config) -> anyhow::Result<Self> {
+        let var = |key: &str| std::env::var(key).ok().filter(|v| !v.is_empty());
+        let setting = |key: &str| config.get(key).filter(|v| !v.is_empty()).map(String::from);
+
+        let name = var(&format!("GIT_{role}_NAME"))
+            .or_else(|| setting("user.name"))
+            .or_else(|| var("USER"))
+            .or_else(|| var("USERNAME"))
+            .unwrap_or_else(|| "unknown".into());
+        let email = var(&format!("GIT_{role}_EMAIL"))
+            .or_else(|| setting("user.email"))
+            .or_else(|| var("EMAIL"))
+            .unwrap_or_else(|| format!("{name}@localhost"));
+
+        Ok(Self {
+            name,
+            email,
+            time: Self::now()?,
+            offset: Self::local_offset(),
+        })
+    }
+
+    fn now() -> anyhow::Result<u64> {
+        Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("Time went backwards")?
+            .as_secs())
+    }
+
+    fn local_offset() -> i32 {
+        chrono::Local::now().offset().local_minus_utc() / 60
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(
+            f,
+            "{} <{}> {} {sign}{:02}{:02}",
+            self.name,
+            self.email,
+            self.time,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+
+    /// parses the `Name <email> <seconds> <+zzzz>` form used in commit headers
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || anyhow::anyhow!("malformed signature [{s}]");
+
+        let (identity, date) = s.rsplit_once('>').ok_or_else(malformed)?;
+        let (name, email) = identity.split_once('<').ok_or_else(malformed)?;
+        let (time, offset) = date.trim().split_once(' ').ok_or_else(malformed)?;
+
+        let (sign, offset) = match offset.split_at_checked(1) {
+            Some(("-", offset)) => (-1, offset),
+            Some(("+", offset)) => (1, offset),
+            _ => return Err(malformed()),
+        };
+        let offset: i32 = offset.parse()?;
+
+        Ok(Self {
+            name: name.trim().into(),
+            email: email.into(),
+            time: time.parse()?,
+            offset: sign * (offset / 100 * 60 + offset % 100),
+        })
+    }
+}
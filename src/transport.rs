@@ -0,0 +1,395 @@
+mod http;
+mod ssh;
+
+use anyhow::Context;
+use std::io::{Cursor, Read};
+
+use crate::refs::Ref;
+use crate::{
+    parse_fetch_response, Acknowledgments, Capabilities, Negotiation, PacketLine,
+    PacketLineBuilder, ShallowUpdate, SidebandReader,
+};
+
+pub use http::HttpTransport;
+pub use ssh::{SshTarget, SshTransport};
+
+/// what a fetch asks of the remote besides the objects themselves
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// number of commits to fetch from the tips, the whole history if `None`
+    pub depth: Option<u32>,
+    /// asks the remote not to send progress messages
+    pub quiet: bool,
+    /// commits the client has without their parents, as listed in its `shallow` file,
+    /// so that the remote doesn't count on the history behind them
+    pub shallow: Vec<String>,
+}
+
+impl FetchOptions {
+    /// whether the response starts with the shallow boundary, which the remote reports
+    /// when deepening or when the client is shallow itself
+    fn deepens(&self) -> bool {
+        self.depth.is_some() || !self.shallow.is_empty()
+    }
+}
+
+/// how the packet-line protocol reaches the upload-pack service of a remote
+pub trait Transport {
+    /// url of the remote, without any credentials, as shown and recorded in the config
+    fn url(&self) -> &str;
+    /// the refs the remote has, along with the capabilities it supports
+    fn advertise_refs(&self) -> anyhow::Result<(Vec<Ref>, Capabilities)>;
+    /// asks the remote for the objects reachable from the hashes in `wants` but not from
+    /// the ones in `haves`, and returns the response to the final request as it comes
+    /// in, for `read_fetch_response` to take apart. the refs must have been advertised
+    /// first.
+    fn fetch(&self, wants: &[String], haves: &[String]) -> anyhow::Result<Box<dyn Read + '_>>;
+}
+
+/// runs a fetch of `wants` from a remote with `capabilities` through `exchange`, which
+/// sends a request, in protocol v2 if asked to, and returns the response.
+///
+/// when every exchange is a request of its own, as over HTTP, and the remote supports
+/// multi_ack_detailed, `haves` are first narrowed down to the ones in common over
+/// rounds of negotiation. returns the response to the final request.
+pub fn negotiate_fetch<F, R>(
+    capabilities: &Capabilities,
+    options: &FetchOptions,
+    wants: &[String],
+    haves: &[String],
+    stateless: bool,
+    mut exchange: F,
+) -> anyhow::Result<R>
+where
+    F: FnMut(Vec<u8>, bool) -> anyhow::Result<R>,
+    R: Read,
+{
+    let v2 = capabilities.version() == 2;
+    let mut plb = PacketLineBuilder::new();
+    if options.quiet && (v2 || capabilities.contains("no-progress")) {
+        plb.no_progress();
+    }
+    let capabilities = capabilities.negotiate();
+    let multi_ack_detailed = capabilities.contains("multi_ack_detailed");
+    plb.capabilities(capabilities);
+    for hex in &options.shallow {
+        plb.shallow(hex.clone());
+    }
+    if let Some(depth) = options.depth {
+        plb.deepen(depth);
+    }
+    for hex in wants {
+        plb.want(hex.clone());
+    }
+    if v2 {
+        for hex in haves {
+            plb.have(hex.clone());
+        }
+        return exchange(plb.build_v2(), true);
+    }
+
+    let mut haves = haves.to_vec();
+    if multi_ack_detailed && stateless {
+        let mut negotiation = Negotiation::new(haves);
+        while let Some(round) = negotiation.next_round() {
+            let mut request = plb.clone();
+            for hex in round {
+                request.have(hex);
+            }
+            request.round();
+            // the response to a round holds nothing but the acks
+            let mut body = Vec::new();
+            exchange(request.build(), false)?
+                .read_to_end(&mut body)
+                .context("failed to read the negotiation response")?;
+            let (_, acks, _) = read_preamble(&body, options.deepens())?;
+            negotiation.receive(acks);
+        }
+        haves = negotiation.common().to_vec();
+    }
+    for hex in haves {
+        plb.have(hex);
+    }
+    exchange(plb.build(), false)
+}
+
+/// reads the response to the final request of a fetch with `options` from a remote
+/// with `capabilities` up to the pack. returns a reader of the pack, which is not read
+/// yet, along with the changes to the shallow boundary, which are only reported when
+/// deepening or fetching into a shallow repository.
+pub fn read_fetch_response<'a, R: Read + 'a>(
+    body: R,
+    capabilities: &Capabilities,
+    options: &FetchOptions,
+) -> anyhow::Result<(Box<dyn Read + 'a>, ShallowUpdate)> {
+    if capabilities.version() == 2 {
+        let (pack, shallow) = parse_fetch_response(body)?;
+        return Ok((Box::new(pack), shallow));
+    }
+    let (preamble, rest) = split_preamble(body)?;
+    let (shallow, _, _) = read_preamble(&preamble, options.deepens())?;
+    let pack: Box<dyn Read + 'a> = match capabilities.negotiate().contains("side-band-64k") {
+        true => Box::new(SidebandReader::new(rest)),
+        false => Box::new(rest),
+    };
+    Ok((pack, shallow))
+}
+
+/// moves the packet lines a protocol v0 response starts with, the shallow boundary
+/// and the acks, off `body` and into a buffer. returns it along with the rest of
+/// `body`, which starts with the pack, side-band multiplexed or not.
+fn split_preamble<R: Read>(mut body: R) -> anyhow::Result<(Vec<u8>, impl Read)> {
+    const PREFIXES: &[&[u8]] = &[b"shallow ", b"unshallow ", b"ACK ", b"NAK"];
+    let mut preamble = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        body.read_exact(&mut len)
+            .context("response ended before the pack")?;
+        if &len == b"PACK" {
+            return Ok((preamble, Cursor::new(len.to_vec()).chain(body)));
+        }
+        let packet = PacketLine::read_from(&mut Cursor::new(len).chain(&mut body))?;
+        if let PacketLine::Data(line) = &packet {
+            if let Some(message) = line.strip_prefix(b"ERR ") {
+                let message = String::from_utf8_lossy(message);
+                anyhow::bail!("remote error: {}", message.trim_end());
+            }
+            if !PREFIXES.iter().any(|prefix| line.starts_with(prefix)) {
+                let mut rest = Vec::new();
+                packet.encode(&mut rest);
+                return Ok((preamble, Cursor::new(rest).chain(body)));
+            }
+        }
+        // the shallow boundary ends with a flush
+        packet.encode(&mut preamble);
+    }
+}
+
+/// every protocol v0 response starts with the shallow boundary when `deepens`, then the
+/// acks. returns both along with the number of bytes they take up.
+fn read_preamble(
+    body: &[u8],
+    deepens: bool,
+) -> anyhow::Result<(ShallowUpdate, Acknowledgments, usize)> {
+    let (shallow, nbytes) = match deepens {
+        true => ShallowUpdate::parse(body)?,
+        false => Default::default(),
+    };
+    let (acks, acks_len) = Acknowledgments::parse(&body[nbytes..])?;
+    Ok((shallow, acks, nbytes + acks_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PacketLine;
+
+    const PACK: &[u8] = include_bytes!("../tests/fixtures/two-commits.pack");
+    const FIRST: &str = "04d46590411d266e2c6312fb8b5eba35d1973522";
+    const SECOND: &str = "e14c4d338a2f1d601d6fcfd7752d9af3c726c943";
+
+    fn data(line: impl AsRef<[u8]>, response: &mut Vec<u8>) {
+        PacketLine::Data(line.as_ref().to_vec()).encode(response);
+    }
+
+    /// the pack on side-band 1, interleaved with progress on side-band 2 the way
+    /// upload-pack sends it
+    fn sideband(response: &mut Vec<u8>) {
+        data(b"\x02Enumerating objects: 8, done.\n", response);
+        for chunk in PACK.chunks(300) {
+            data([&[1u8][..], chunk].concat(), response);
+            data(b"\x02Counting objects: 100% (8/8)\r", response);
+        }
+        data(b"\x02Total 8 (delta 0), reused 0 (delta 0)\n", response);
+        PacketLine::Flush.encode(response);
+    }
+
+    fn read_all(mut pack: impl Read) -> Vec<u8> {
+        let mut buf = Vec::new();
+        pack.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_the_pack_after_a_realistic_preamble() {
+        let capabilities = Capabilities::parse(
+            "multi_ack_detailed side-band-64k thin-pack ofs-delta shallow no-progress \
+             symref=HEAD:refs/heads/master agent=git/2.43.0",
+        );
+
+        // a clone: nothing in common
+        let mut response = Vec::new();
+        data("NAK\n", &mut response);
+        sideband(&mut response);
+        let (pack, shallow) = read_fetch_response(
+            Cursor::new(response),
+            &capabilities,
+            &FetchOptions::default(),
+        )
+        .unwrap();
+        let pack = read_all(pack);
+        assert_eq!(pack, PACK);
+        assert!(shallow.shallow.is_empty());
+        assert_eq!(crate::Packet::from_bytes(&pack).unwrap().objects.len(), 8);
+
+        // a shallow fetch with a have the server acknowledged in an earlier round
+        let mut response = Vec::new();
+        data(format!("shallow {FIRST}\n"), &mut response);
+        PacketLine::Flush.encode(&mut response);
+        data(format!("ACK {SECOND}\n"), &mut response);
+        sideband(&mut response);
+        let options = FetchOptions {
+            depth: Some(1),
+            ..Default::default()
+        };
+        let (pack, shallow) =
+            read_fetch_response(Cursor::new(response), &capabilities, &options).unwrap();
+        let pack = read_all(pack);
+        assert_eq!(pack, PACK);
+        assert_eq!(shallow.shallow, [FIRST]);
+        assert!(shallow.unshallow.is_empty());
+
+        // without side-band the pack follows the preamble as is
+        let plain = Capabilities::parse("multi_ack_detailed ofs-delta");
+        let mut response = Vec::new();
+        data(format!("ACK {SECOND} common\n"), &mut response);
+        data(format!("ACK {SECOND} ready\n"), &mut response);
+        data("NAK\n", &mut response);
+        data(format!("ACK {SECOND}\n"), &mut response);
+        response.extend(PACK);
+        let (pack, _) =
+            read_fetch_response(Cursor::new(response), &plain, &FetchOptions::default()).unwrap();
+        let pack = read_all(pack);
+        assert_eq!(pack, PACK);
+    }
+
+    #[test]
+    fn reports_remote_errors_on_the_error_band() {
+        let capabilities = Capabilities::parse("side-band-64k");
+        let mut response = Vec::new();
+        data("NAK\n", &mut response);
+        data(b"\x03upload-pack: not our ref\n", &mut response);
+        let (mut pack, _) = read_fetch_response(
+            Cursor::new(response),
+            &capabilities,
+            &FetchOptions::default(),
+        )
+        .unwrap();
+        let err = pack.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "remote error: upload-pack: not our ref");
+
+        // before the pack, errors come in packets of their own
+        let mut response = Vec::new();
+        data("ERR upload-pack: not our ref\n", &mut response);
+        let Err(err) = read_fetch_response(
+            Cursor::new(response),
+            &capabilities,
+            &FetchOptions::default(),
+        ) else {
+            panic!("the error packet was taken for the pack");
+        };
+        assert_eq!(err.to_string(), "remote error: upload-pack: not our ref");
+    }
+
+    #[test]
+    fn runs_negotiation_rounds_over_stateless_requests() {
+        let capabilities = Capabilities::parse("multi_ack_detailed side-band-64k ofs-delta");
+        let haves: Vec<String> = (1..=40).map(|i| format!("{i:040x}")).collect();
+        let common = haves[35].clone();
+        let mut requests = Vec::new();
+        let response = negotiate_fetch(
+            &capabilities,
+            &FetchOptions::default(),
+            &[SECOND.into()],
+            &haves,
+            true,
+            |request, v2| {
+                assert!(!v2);
+                let request = String::from_utf8(request).unwrap();
+                let mut response = Vec::new();
+                match requests.len() {
+                    0 => data("NAK\n", &mut response),
+                    1 => {
+                        data(format!("ACK {common} common\n"), &mut response);
+                        data(format!("ACK {common} ready\n"), &mut response);
+                        data("NAK\n", &mut response);
+                    }
+                    _ => {
+                        data(format!("ACK {common}\n"), &mut response);
+                        sideband(&mut response);
+                    }
+                }
+                requests.push(request);
+                Ok(Cursor::new(response))
+            },
+        )
+        .unwrap();
+
+        assert_eq!(requests.len(), 3);
+        let have_lines = |request: &str| request.matches("have ").count();
+        assert_eq!(have_lines(&requests[0]), 32);
+        assert!(requests[0].ends_with("0000"));
+        assert_eq!(have_lines(&requests[1]), 8);
+        // only the common have goes along with `done`
+        assert_eq!(have_lines(&requests[2]), 1);
+        assert!(requests[2].contains(&format!("have {common}")));
+        assert!(requests[2].ends_with("0009done\n"));
+        let (pack, _) =
+            read_fetch_response(response, &capabilities, &FetchOptions::default()).unwrap();
+        let pack = read_all(pack);
+        assert_eq!(pack, PACK);
+
+        // over a connection that stays open there are no rounds, all haves go at once
+        let mut requests = Vec::new();
+        negotiate_fetch(
+            &capabilities,
+            &FetchOptions::default(),
+            &[SECOND.into()],
+            &haves,
+            false,
+            |request, _| {
+                requests.push(String::from_utf8(request).unwrap());
+                Ok(std::io::empty())
+            },
+        )
+        .unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(have_lines(&requests[0]), 40);
+    }
+
+    #[test]
+    fn sends_the_shallow_boundary() {
+        let capabilities = Capabilities::parse("multi_ack_detailed side-band-64k shallow");
+        let options = FetchOptions {
+            shallow: vec![FIRST.into()],
+            ..Default::default()
+        };
+        let mut request = Vec::new();
+        let response = negotiate_fetch(
+            &capabilities,
+            &options,
+            &[SECOND.into()],
+            &[],
+            true,
+            |body, _| {
+                request = body;
+                // an empty shallow section, as a remote sends without deepening
+                let mut response = Vec::new();
+                PacketLine::Flush.encode(&mut response);
+                data("NAK\n", &mut response);
+                sideband(&mut response);
+                Ok(Cursor::new(response))
+            },
+        )
+        .unwrap();
+        let request = String::from_utf8(request).unwrap();
+        let want = request.find("want ").unwrap();
+        let shallow = request.find(&format!("0035shallow {FIRST}\n")).unwrap();
+        assert!(want < shallow && shallow < request.find("0000").unwrap());
+
+        let (pack, update) = read_fetch_response(response, &capabilities, &options).unwrap();
+        let pack = read_all(pack);
+        assert_eq!(pack, PACK);
+        assert!(update.shallow.is_empty() && update.unshallow.is_empty());
+    }
+}
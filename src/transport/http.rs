@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Context};
+use std::cell::OnceCell;
+use std::io::Read;
+use std::time::Duration;
+
+use super::{FetchOptions, Transport};
+use crate::refs::Ref;
+use crate::{Capabilities, IntoPackeLineIterator};
+
+/// talks to a remote over smart HTTP
+pub struct HttpTransport {
+    client: reqwest::blocking::Client,
+    /// the url as given, stripped of its credentials
+    url: String,
+    options: FetchOptions,
+    /// the url the remote was eventually found at once its refs were requested
+    base_url: OnceCell<String>,
+    /// what the remote advertised along with its refs
+    capabilities: OnceCell<Capabilities>,
+}
+
+impl HttpTransport {
+    /// for the remote at `url`, which may carry credentials, to fetch with `options`.
+    /// nothing is sent until the refs are asked for.
+    pub fn new(url: &str, options: &FetchOptions) -> anyhow::Result<Self> {
+        let (client, url) = http_client(url)?;
+        Ok(Self {
+            client,
+            url,
+            options: options.clone(),
+            base_url: OnceCell::new(),
+            capabilities: OnceCell::new(),
+        })
+    }
+
+    fn base_url(&self) -> &str {
+        self.base_url.get().unwrap_or(&self.url)
+    }
+}
+
+impl Transport for HttpTransport {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn advertise_refs(&self) -> anyhow::Result<(Vec<Ref>, Capabilities)> {
+        let (base_url, response) = request_info_refs(&self.client, &self.url)?;
+        let _ = self.base_url.set(base_url);
+        let body = response.bytes()?;
+        let lines = body
+            .into_packet_line_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("malformed ref advertisement")?;
+        if lines.first().and_then(|p| p.data()) == Some(b"version 2\n".as_slice()) {
+            let capabilities = Capabilities::parse_v2(lines)?;
+            let request = crate::encode_command(
+                "ls-refs",
+                &[
+                    "peel",
+                    "symrefs",
+                    "ref-prefix HEAD",
+                    "ref-prefix refs/heads/",
+                    "ref-prefix refs/tags/",
+                ],
+            );
+            let body = post_upload_pack(&self.client, self.base_url(), request, true)?.bytes()?;
+            let lines = body
+                .into_packet_line_iter()
+                .collect::<anyhow::Result<Vec<_>>>()
+                .context("malformed ls-refs response")?;
+            anyhow::ensure!(
+                lines.last().is_some_and(|p| p.is_flush()),
+                "ls-refs response is truncated"
+            );
+            let _ = self.capabilities.set(capabilities.clone());
+            return Ok((crate::refs::parse_ls_refs(lines)?, capabilities));
+        }
+        // the service announcement and the refs are each terminated by a flush packet
+        anyhow::ensure!(
+            lines.iter().filter(|p| p.is_flush()).count() >= 2,
+            "ref advertisement is truncated"
+        );
+        let (refs, capabilities) = crate::refs::parse_advertisement(
+            lines
+                .into_iter()
+                .skip_while(|p| !p.is_flush())
+                .skip(1)
+                .take_while(|p| !p.is_flush()),
+        )?;
+        let _ = self.capabilities.set(capabilities.clone());
+        Ok((refs, capabilities))
+    }
+
+    /// every request is one of its own, so the haves are negotiated over several
+    fn fetch(&self, wants: &[String], haves: &[String]) -> anyhow::Result<Box<dyn Read + '_>> {
+        let capabilities = self.capabilities.get().ok_or(anyhow!(
+            "the refs of the remote must be read before fetching"
+        ))?;
+        crate::negotiate_fetch(
+            capabilities,
+            &self.options,
+            wants,
+            haves,
+            true,
+            |request, v2| post_upload_pack(&self.client, self.base_url(), request, v2),
+        )
+        .map(|response| Box::new(response) as Box<dyn Read>)
+    }
+}
+
+/// requests the ref advertisement of the remote at `url`. redirects are followed and a
+/// `.git` suffix is tried if the url as given isn't found, so returns the base url of
+/// the remote as it was eventually found along with the response.
+fn request_info_refs(
+    client: &reqwest::blocking::Client,
+    url: &str,
+) -> anyhow::Result<(String, reqwest::blocking::Response)> {
+    let url = url.trim_end_matches('/');
+    let mut candidates = vec![url.to_string()];
+    if !url.ends_with(".git") {
+        candidates.push(format!("{url}.git"));
+    }
+    let mut response = None;
+    for candidate in candidates {
+        // servers that don't speak protocol v2 ignore the header and advertise their refs
+        let r = send(
+            client
+                .get(format!("{candidate}/info/refs"))
+                .query(&[("service", "git-upload-pack")])
+                .header("Git-Protocol", "version=2"),
+        )?;
+        let found = r.status() != reqwest::StatusCode::NOT_FOUND;
+        response = Some(r);
+        if found {
+            break;
+        }
+    }
+    let response = check_status(response.expect("there is at least one candidate"))?;
+
+    let mut final_url = response.url().clone();
+    final_url.set_query(None);
+    let base = final_url
+        .as_str()
+        .strip_suffix("/info/refs")
+        .ok_or(anyhow!(
+            "remote redirected to an unexpected url {final_url}"
+        ))?
+        .to_string();
+    Ok((base, response))
+}
+
+/// sends `request` to the remote's upload-pack service, speaking protocol v2 if `v2`
+fn post_upload_pack(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    request: Vec<u8>,
+    v2: bool,
+) -> anyhow::Result<reqwest::blocking::Response> {
+    let mut builder = client
+        .post(format!("{url}/git-upload-pack"))
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            "application/x-git-upload-pack-request",
+        )
+        .header(
+            reqwest::header::ACCEPT,
+            "application/x-git-upload-pack-result",
+        );
+    if v2 {
+        builder = builder.header("Git-Protocol", "version=2");
+    }
+    check_status(send(builder.body(request))?)
+}
+
+/// turns an unsuccessful `response` into an error carrying its status along with the
+/// start of its body, which is often where the server explains what went wrong
+fn check_status(
+    response: reqwest::blocking::Response,
+) -> anyhow::Result<reqwest::blocking::Response> {
+    const SNIPPET_LEN: usize = 200;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let url = response.url().clone();
+    let body = response.bytes().unwrap_or_default();
+    let snippet = String::from_utf8_lossy(&body[..body.len().min(SNIPPET_LEN)]);
+    let snippet = snippet.trim();
+    match snippet.is_empty() {
+        true => anyhow::bail!("server returned {status} for {url}"),
+        false => anyhow::bail!("server returned {status} for {url}: {snippet}"),
+    }
+}
+
+/// builds the client used to talk to the remote at `url`. the connect and overall
+/// request timeouts, in seconds, can be set through `GIT_HTTP_CONNECT_TIMEOUT` and
+/// `GIT_HTTP_TIMEOUT`.
+///
+/// requests are authenticated with the credentials embedded in `url`, or else the ones
+/// in `GIT_USERNAME` and `GIT_PASSWORD`. returns the client along with `url` stripped
+/// of its credentials so it is safe to show.
+fn http_client(url: &str) -> anyhow::Result<(reqwest::blocking::Client, String)> {
+    use base64::Engine;
+    use percent_encoding::percent_decode_str;
+
+    // the url isn't echoed back here as it may hold credentials
+    let mut url = reqwest::Url::parse(url).context("invalid remote url")?;
+    let username = match url.username() {
+        "" => std::env::var("GIT_USERNAME").ok(),
+        username => Some(percent_decode_str(username).decode_utf8()?.into_owned()),
+    };
+    let password = match url.password() {
+        None => std::env::var("GIT_PASSWORD").ok(),
+        Some(password) => Some(percent_decode_str(password).decode_utf8()?.into_owned()),
+    };
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    if username.is_some() || password.is_some() {
+        let credentials = format!(
+            "{}:{}",
+            username.unwrap_or_default(),
+            password.unwrap_or_default()
+        );
+        let token = base64::engine::general_purpose::STANDARD.encode(credentials);
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Basic {token}"))?;
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+    let client = reqwest::blocking::Client::builder()
+        .default_headers(headers)
+        .connect_timeout(Duration::from_secs(env_or("GIT_HTTP_CONNECT_TIMEOUT", 10)?))
+        .timeout(Duration::from_secs(env_or("GIT_HTTP_TIMEOUT", 300)?))
+        .build()
+        .context("failed to build the http client")?;
+    Ok((client, url.into()))
+}
+
+/// sends `request`, retrying failed connections, timeouts and 5xx responses with an
+/// exponential backoff up to `GIT_HTTP_RETRIES` times. other errors are not transient,
+/// and other error statuses are left to the caller.
+fn send(request: reqwest::blocking::RequestBuilder) -> anyhow::Result<reqwest::blocking::Response> {
+    let retries = env_or("GIT_HTTP_RETRIES", 3)?;
+    let mut attempt = 0;
+    loop {
+        let result = request
+            .try_clone()
+            .ok_or(anyhow!("request can't be retried"))?
+            .send();
+        let transient = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(e) => e.is_connect() || e.is_timeout(),
+        };
+        if !transient || attempt >= retries {
+            return Ok(result?);
+        }
+        std::thread::sleep(Duration::from_millis(250 << attempt.min(6)));
+        attempt += 1;
+    }
+}
+
+fn env_or(name: &str, default: u64) -> anyhow::Result<u64> {
+    match std::env::var(name) {
+        Ok(value) => value.parse().context(format!("invalid {name} [{value}]")),
+        Err(_) => Ok(default),
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{anyhow, Context};
+use std::cell::{OnceCell, RefCell};
+use std::io::Read;
+
+use super::{FetchOptions, Transport};
+use crate::refs::Ref;
+use crate::{Capabilities, PacketLine};
+
+/// where an `ssh://[user@]host[:port]/path` url, or its scp-like `[user@]host:path`
+/// form, points at
+pub struct SshTarget {
+    /// `host` or `user@host`
+    host: String,
+    port: Option<String>,
+    path: String,
+}
+
+impl SshTarget {
+    /// `None` if `url` is not an ssh url. a host or port that ssh would take for an
+    /// option, such as `-oProxyCommand=...`, is refused like git does.
+    pub fn parse(url: &str) -> anyhow::Result<Option<Self>> {
+        let Some(target) = Self::split(url) else {
+            return Ok(None);
+        };
+        anyhow::ensure!(
+            !target.host.starts_with('-'),
+            "strange hostname '{}' blocked",
+            target.host
+        );
+        if let Some(port) = &target.port {
+            anyhow::ensure!(!port.starts_with('-'), "strange port '{port}' blocked");
+        }
+        Ok(Some(target))
+    }
+
+    fn split(url: &str) -> Option<Self> {
+        if let Some(rest) = url
+            .strip_prefix("ssh://")
+            .or(url.strip_prefix("git+ssh://"))
+        {
+            let (authority, path) = rest.split_once('/')?;
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port.to_owned())),
+                None => (authority, None),
+            };
+            // `/~user/repo` is relative to the home directory of `user`
+            let path = match path.starts_with('~') {
+                true => path.to_owned(),
+                false => format!("/{path}"),
+            };
+            return Some(Self {
+                host: host.into(),
+                port,
+                path,
+            });
+        }
+        if url.contains("://") {
+            return None;
+        }
+        let (host, path) = url.split_once(':')?;
+        if host.is_empty() || host.contains('/') || path.is_empty() {
+            return None;
+        }
+        Some(Self {
+            host: host.into(),
+            port: None,
+            path: path.into(),
+        })
+    }
+}
+
+/// talks to a `git-upload-pack` running on the other end of an ssh connection over
+/// its stdin and stdout. the ssh client run is `$GIT_SSH`, or `ssh` if unset.
+pub struct SshTransport {
+    url: String,
+    options: FetchOptions,
+    child: RefCell<std::process::Child>,
+    stdout: RefCell<std::io::BufReader<std::process::ChildStdout>>,
+    /// what the remote advertised, known once its refs are read
+    capabilities: OnceCell<Capabilities>,
+}
+
+impl SshTransport {
+    /// starts the ssh client to run `git-upload-pack` at `target`, which `url` was
+    /// parsed into, to fetch with `options`
+    pub fn connect(url: &str, target: &SshTarget, options: &FetchOptions) -> anyhow::Result<Self> {
+        let program = std::env::var_os("GIT_SSH").unwrap_or_else(|| "ssh".into());
+        let mut command = std::process::Command::new(&program);
+        if let Some(port) = &target.port {
+            command.args(["-p", port]);
+        }
+        // the remote shell splits the command, so the path is quoted for it
+        let path = target.path.replace('\'', "'\\''");
+        command
+            .arg(&target.host)
+            .arg(format!("git-upload-pack '{path}'"))
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped());
+        let mut child = command
+            .spawn()
+            .context(format!("failed to run {program:?}"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(anyhow!("failed to read from {program:?}"))?;
+        Ok(Self {
+            url: url.into(),
+            options: options.clone(),
+            child: RefCell::new(child),
+            stdout: RefCell::new(std::io::BufReader::new(stdout)),
+            capabilities: OnceCell::new(),
+        })
+    }
+}
+
+impl Transport for SshTransport {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// reads the refs and capabilities the remote starts with. unlike over HTTP, no
+    /// service announcement precedes them.
+    fn advertise_refs(&self) -> anyhow::Result<(Vec<Ref>, Capabilities)> {
+        let mut lines = Vec::new();
+        loop {
+            match PacketLine::read_from(&mut *self.stdout.borrow_mut())
+                .context("failed to read the ref advertisement")?
+            {
+                PacketLine::Flush => break,
+                line => lines.push(line),
+            }
+        }
+        let (refs, capabilities) = crate::refs::parse_advertisement(lines)?;
+        let _ = self.capabilities.set(capabilities.clone());
+        Ok((refs, capabilities))
+    }
+
+    /// sends the fetch request and hands out the response, which lasts until the remote
+    /// hangs up, so the conversation ends with the first fetch. the remote only speaks protocol v2 when
+    /// asked through the environment, which isn't done.
+    fn fetch(&self, wants: &[String], haves: &[String]) -> anyhow::Result<Box<dyn Read + '_>> {
+        use std::io::Write;
+
+        let capabilities = self.capabilities.get().ok_or(anyhow!(
+            "the refs of the remote must be read before fetching"
+        ))?;
+        crate::negotiate_fetch(
+            capabilities,
+            &self.options,
+            wants,
+            haves,
+            false,
+            |request, _| {
+                let mut child = self.child.borrow_mut();
+                let mut stdin = child
+                    .stdin
+                    .take()
+                    .ok_or(anyhow!("failed to write to the ssh connection"))?;
+                stdin.write_all(&request)?;
+                drop(stdin);
+                Ok(Box::new(SshResponse {
+                    stdout: self.stdout.borrow_mut(),
+                    child,
+                }) as Box<dyn Read>)
+            },
+        )
+    }
+}
+
+/// the output of ssh once the request is sent, which ends when the remote hangs up
+struct SshResponse<'a> {
+    stdout: std::cell::RefMut<'a, std::io::BufReader<std::process::ChildStdout>>,
+    child: std::cell::RefMut<'a, std::process::Child>,
+}
+
+impl Read for SshResponse<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                let message = format!("the remote end hung up: {status}");
+                return Err(std::io::Error::other(message));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// without a request sent, the remote waits for one. a flush tells it there is none.
+impl Drop for SshTransport {
+    fn drop(&mut self) {
+        use std::io::Write;
+
+        let child = self.child.get_mut();
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(b"0000");
+        }
+        let _ = child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_ssh_hosts_that_look_like_options() {
+        for url in [
+            "ssh://-oProxyCommand=touch${IFS}pwned/repo.git",
+            "git+ssh://-oProxyCommand=sh/repo.git",
+            "-oProxyCommand=touch pwned:repo.git",
+            "ssh://host:-oProxyCommand=sh/repo.git",
+        ] {
+            let err = SshTarget::parse(url).err().unwrap();
+            assert!(err.to_string().contains("blocked"), "{url}: {err}");
+        }
+        let target = SshTarget::parse("ssh://git@example.com:2222/~me/repo.git");
+        let target = target.unwrap().unwrap();
+        assert_eq!(target.host, "git@example.com");
+        assert_eq!(target.port.as_deref(), Some("2222"));
+        assert_eq!(target.path, "~me/repo.git");
+        let target = SshTarget::parse("git@example.com:repo.git")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (target.host.as_str(), target.path.as_str()),
+            ("git@example.com", "repo.git")
+        );
+        assert!(SshTarget::parse("https://example.com/repo.git")
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::{Commit, Object};
+
+/// walks the history down from a set of commits, loading each reachable commit once.
+/// commits come out newest first by committer date, so a commit always comes before
+/// its parents unless clocks were skewed. commits sharing a date come out by
+/// descending hash, which keeps the order deterministic.
+///
+/// the commits at the boundary of a shallow clone come out without parents, since
+/// those were never fetched.
+pub struct RevWalk {
+    git_dir: PathBuf,
+    queue: BinaryHeap<(u64, String)>,
+    /// commits waiting in the queue, along with the ones already walked
+    seen: HashMap<String, Option<Commit>>,
+    shallow: HashSet<String>,
+}
+
+impl RevWalk {
+    /// starts a walk from `heads`, which may also be annotated tags of commits
+    pub fn new<I>(git_dir: &Path, heads: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut walk = Self {
+            git_dir: git_dir.to_owned(),
+            queue: BinaryHeap::new(),
+            seen: HashMap::new(),
+            shallow: crate::repo::shallow_commits(git_dir)?,
+        };
+        for head in heads {
+            let (head, _) = crate::refs::peel_to_commit(git_dir, &head)?;
+            walk.push(head)?;
+        }
+        Ok(walk)
+    }
+
+    fn push(&mut self, hash: String) -> anyhow::Result<()> {
+        if self.seen.contains_key(&hash) {
+            return Ok(());
+        }
+        let mut commit = Commit::try_from(Object::load_in(&self.git_dir, &hash)?)?;
+        if self.shallow.contains(&hash) {
+            commit.parents.clear();
+        }
+        self.queue.push((commit.committer.time, hash.clone()));
+        self.seen.insert(hash, Some(commit));
+        Ok(())
+    }
+}
+
+impl Iterator for RevWalk {
+    type Item = anyhow::Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, hash) = self.queue.pop()?;
+        let commit = self.seen.get_mut(&hash)?.take()?;
+        for parent in &commit.parents {
+            if let Err(e) = self.push(parent.clone()) {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok((hash, commit)))
+    }
+}